@@ -21,8 +21,11 @@ const USAGE: &str = "
 Usage: bao hash [<input>] [--encoded]
        bao encode [<input>] [<output>]
        bao decode <hash> [<input>] [<output>] [--start=<offset>]
-       bao slice <start> <len> [<input>] [<output>]
-       bao decode-slice <hash> <start> <len> [<input>] [<output>]
+       bao slice [<input>] [<output>] --range=<start:len>...
+       bao decode-slice <hash> [<input>] [<output>] --range=<start:len>...
+       bao mount <hash> <input> <mountpoint>
+       bao archive <output> <member>...
+       bao unarchive <hash> <input> <dest>
        bao (--help | --version)
 ";
 
@@ -33,13 +36,18 @@ struct Args {
     cmd_hash: bool,
     cmd_slice: bool,
     cmd_decode_slice: bool,
+    cmd_mount: bool,
+    cmd_archive: bool,
+    cmd_unarchive: bool,
     arg_input: Option<PathBuf>,
     arg_output: Option<PathBuf>,
     arg_hash: String,
-    arg_start: u64,
-    arg_len: u64,
+    arg_mountpoint: Option<PathBuf>,
+    arg_member: Vec<PathBuf>,
+    arg_dest: Option<PathBuf>,
     flag_encoded: bool,
     flag_help: bool,
+    flag_range: Vec<String>,
     flag_start: Option<u64>,
     flag_version: bool,
 }
@@ -70,6 +78,12 @@ fn main() -> Result<(), Error> {
         slice(&args, in_file, out_file)?;
     } else if args.cmd_decode_slice {
         decode_slice(&args, in_file, out_file)?;
+    } else if args.cmd_mount {
+        mount(&args)?;
+    } else if args.cmd_archive {
+        archive(&args)?;
+    } else if args.cmd_unarchive {
+        unarchive(&args)?;
     } else {
         unreachable!();
     }
@@ -96,10 +110,20 @@ fn hash_encoded(_args: &Args, mut in_file: File) -> Result<(), Error> {
     Ok(())
 }
 
+// Below this size, the thread-spawning overhead of the parallel encoder isn't worth it.
+const PARALLEL_ENCODE_THRESHOLD: usize = 1 << 20;
+
 fn encode(_args: &Args, mut in_file: File, out_file: File) -> Result<(), Error> {
     if let Some(in_map) = maybe_memmap_input(&in_file)? {
         let target_len = bao::encode::encoded_size(in_map.len() as u64);
         if let Some(mut out_map) = maybe_memmap_output(&out_file, target_len)? {
+            #[cfg(feature = "rayon")]
+            {
+                if in_map.len() >= PARALLEL_ENCODE_THRESHOLD {
+                    bao::encode::encode_parallel(&in_map, &mut out_map);
+                    return Ok(());
+                }
+            }
             bao::encode::encode(&in_map, &mut out_map);
             return Ok(());
         }
@@ -135,21 +159,130 @@ fn decode(args: &Args, in_file: File, mut out_file: File) -> Result<(), Error> {
     Ok(())
 }
 
-fn slice(args: &Args, in_file: File, mut out_file: File) -> Result<(), Error> {
+fn slice(args: &Args, mut in_file: File, mut out_file: File) -> Result<(), Error> {
     // Slice extraction requires seek.
     confirm_real_file(&in_file, "slicing input")?;
-    let mut reader = bao::decode::SliceExtractor::new(in_file, args.arg_start, args.arg_len);
-    io::copy(&mut reader, &mut out_file)?;
+    let content_len = read_content_len(&mut in_file)?;
+    let ranges = parse_ranges(args)?;
+    let mut extractor = bao::encode::SliceExtractor::new(in_file, content_len, &ranges);
+    io::copy(&mut extractor, &mut out_file)?;
     Ok(())
 }
 
 fn decode_slice(args: &Args, in_file: File, mut out_file: File) -> Result<(), Error> {
     let hash = parse_hash(&args)?;
-    let mut reader = bao::decode::SliceReader::new(in_file, &hash, args.arg_start, args.arg_len);
+    let ranges = parse_ranges(args)?;
+    let mut reader = bao::decode::SliceReader::new(in_file, hash, ranges);
     allow_broken_pipe(io::copy(&mut reader, &mut out_file))?;
     Ok(())
 }
 
+/// Read and decode the 8-byte content-length header a combined encoding starts with. There's no
+/// need to seek back afterwards: `encode::SliceExtractor` writes the slice's header itself from
+/// `content_len` and re-seeks to the offset of every node it reads, so it never relies on
+/// `in_file`'s position.
+fn read_content_len(in_file: &mut File) -> Result<u64, Error> {
+    let mut header = [0; bao::hash::HEADER_SIZE];
+    in_file.read_exact(&mut header)?;
+    Ok(bao::hash::decode_len(header))
+}
+
+/// Parse one or more `--range=<start>:<len>` flags into the sorted, disjoint `(start, len)` pairs
+/// that `encode::SliceExtractor`/`decode::SliceReader` require, so that fetching several scattered
+/// byte ranges takes one slice instead of one per range.
+fn parse_ranges(args: &Args) -> Result<Vec<(u64, u64)>, Error> {
+    let mut ranges = Vec::with_capacity(args.flag_range.len());
+    for range in &args.flag_range {
+        let mut parts = range.splitn(2, ':');
+        let start = parts
+            .next()
+            .ok_or_else(|| err_msg("--range must be <start>:<len>"))?;
+        let len = parts
+            .next()
+            .ok_or_else(|| err_msg("--range must be <start>:<len>"))?;
+        let start: u64 = start.parse().map_err(|_| err_msg("invalid --range start"))?;
+        let len: u64 = len.parse().map_err(|_| err_msg("invalid --range len"))?;
+        ranges.push((start, len));
+    }
+    ranges.sort();
+    for pair in ranges.windows(2) {
+        let (start, len) = pair[0];
+        if start.saturating_add(len) > pair[1].0 {
+            return Err(err_msg("--range values must not overlap"));
+        }
+    }
+    Ok(ranges)
+}
+
+fn mount(args: &Args) -> Result<(), Error> {
+    let hash = parse_hash(args)?;
+    let input_path = args
+        .arg_input
+        .as_ref()
+        .ok_or_else(|| err_msg("mount requires a real encoded input file, not a pipe"))?;
+    let mountpoint = args
+        .arg_mountpoint
+        .as_ref()
+        .ok_or_else(|| err_msg("mount requires a mountpoint"))?;
+    bao::mount::mount(hash, input_path, mountpoint)?;
+    Ok(())
+}
+
+fn archive(args: &Args) -> Result<(), Error> {
+    let output_path = args
+        .arg_output
+        .as_ref()
+        .ok_or_else(|| err_msg("archive requires an output path"))?;
+    let mut members = Vec::with_capacity(args.arg_member.len());
+    for member_path in &args.arg_member {
+        let mut content = Vec::new();
+        File::open(member_path)?.read_to_end(&mut content)?;
+        let name = member_path
+            .file_name()
+            .ok_or_else(|| err_msg("member path has no file name"))?
+            .to_string_lossy()
+            .into_owned();
+        members.push((name, content));
+    }
+    let out_file = File::create(output_path)?;
+    let archive_hash = bao::archive::archive(&members, out_file)?;
+    println!("{}", hex::encode(archive_hash));
+    Ok(())
+}
+
+fn unarchive(args: &Args) -> Result<(), Error> {
+    let hash = parse_hash(args)?;
+    let input_path = args
+        .arg_input
+        .as_ref()
+        .ok_or_else(|| err_msg("unarchive requires a real archive input file, not a pipe"))?;
+    let dest = args
+        .arg_dest
+        .as_ref()
+        .ok_or_else(|| err_msg("unarchive requires a destination directory"))?;
+
+    let mut catalog_file = File::open(input_path)?;
+    let catalog = bao::archive::read_catalog(&mut catalog_file, &hash)?;
+
+    std::fs::create_dir_all(dest)?;
+    for entry in &catalog.entries {
+        let out_path = bao::archive::safe_member_path(dest, &entry.path)?;
+        let mut reader = bao::archive::extract_member(&mut catalog_file, entry);
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        if content.len() as u64 != entry.content_length {
+            return Err(err_msg(format!(
+                "archive entry {} extracted to {} bytes, expected {}",
+                entry.path,
+                content.len(),
+                entry.content_length
+            )));
+        }
+        std::fs::write(out_path, &content)?;
+    }
+    Ok(())
+}
+
 fn in_out_files(args: &Args) -> Result<(File, File), Error> {
     let in_file = if let Some(ref input_path) = args.arg_input {
         if input_path == Path::new("-") {
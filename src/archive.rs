@@ -0,0 +1,151 @@
+//! A multi-file archive format built on top of bao's single-stream encoding, analogous to a tar
+//! stream but with bao's integrity guarantees end to end.
+//!
+//! An archive is a bao-encoded catalog followed by each member's own bao-encoded content, back to
+//! back. The catalog is authenticated first, which means the full set of member paths and lengths
+//! is known and verified before any member's bytes are trusted or extracted; each member can then
+//! be decoded independently via its own `subtree_hash`, without decoding the rest of the archive
+//! (see `extract_member` for where its input must be positioned, and when seeking works).
+
+use decode;
+use encode;
+use hash::Hash;
+
+use std::io;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+/// One entry in an archive's catalog: a member's path, its decoded length, and the root hash of
+/// its own bao encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    pub path: String,
+    pub content_length: u64,
+    pub subtree_hash: Hash,
+}
+
+/// The authenticated table of contents of an archive. The archive's own identity is the root hash
+/// of the bao-encoded, serialized catalog.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Catalog {
+    pub entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// A simple length-prefixed serialization: entry count, then for each entry its path length,
+    /// UTF-8 path bytes, content length, and hash, all little-endian.
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for entry in &self.entries {
+            let path_bytes = entry.path.as_bytes();
+            out.extend_from_slice(&(path_bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(path_bytes);
+            out.extend_from_slice(&entry.content_length.to_le_bytes());
+            out.extend_from_slice(&entry.subtree_hash);
+        }
+        out
+    }
+
+    fn deserialize(mut bytes: &[u8]) -> io::Result<Self> {
+        let bad = || io::Error::new(io::ErrorKind::InvalidData, "truncated archive catalog");
+        let count = read_u64(&mut bytes).ok_or_else(bad)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let path_len = read_u64(&mut bytes).ok_or_else(bad)? as usize;
+            if bytes.len() < path_len {
+                return Err(bad());
+            }
+            let path = String::from_utf8(bytes[..path_len].to_vec())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "non-UTF-8 path"))?;
+            bytes = &bytes[path_len..];
+            let content_length = read_u64(&mut bytes).ok_or_else(bad)?;
+            if bytes.len() < 32 {
+                return Err(bad());
+            }
+            let mut subtree_hash = Hash::default();
+            subtree_hash.copy_from_slice(&bytes[..32]);
+            bytes = &bytes[32..];
+            entries.push(CatalogEntry {
+                path,
+                content_length,
+                subtree_hash,
+            });
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn read_u64(bytes: &mut &[u8]) -> Option<u64> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let mut array = [0; 8];
+    array.copy_from_slice(&bytes[..8]);
+    *bytes = &bytes[8..];
+    Some(u64::from_le_bytes(array))
+}
+
+/// Pack `members` (path, content bytes) into a single archive written to `out`. Returns the
+/// archive's identity hash: the root hash of the bao-encoded catalog.
+pub fn archive<W: Write>(members: &[(String, Vec<u8>)], mut out: W) -> io::Result<Hash> {
+    let mut entries = Vec::with_capacity(members.len());
+    let mut encoded_members = Vec::with_capacity(members.len());
+    for (path, content) in members {
+        let encoded_size = encode::encoded_size(content.len() as u64) as usize;
+        let mut encoded = vec![0; encoded_size];
+        let subtree_hash = encode::encode(content, &mut encoded);
+        entries.push(CatalogEntry {
+            path: path.clone(),
+            content_length: content.len() as u64,
+            subtree_hash,
+        });
+        encoded_members.push(encoded);
+    }
+
+    let catalog_bytes = Catalog { entries }.serialize();
+    let catalog_encoded_size = encode::encoded_size(catalog_bytes.len() as u64) as usize;
+    let mut catalog_encoded = vec![0; catalog_encoded_size];
+    let archive_hash = encode::encode(&catalog_bytes, &mut catalog_encoded);
+
+    out.write_all(&catalog_encoded)?;
+    for encoded in &encoded_members {
+        out.write_all(encoded)?;
+    }
+    Ok(archive_hash)
+}
+
+/// Verify the catalog of an archive produced by `archive` against `archive_hash`, without
+/// touching any member's content. Returns the authenticated catalog.
+pub fn read_catalog<R: Read>(mut input: R, archive_hash: &Hash) -> io::Result<Catalog> {
+    let mut reader = decode::Reader::new(&mut input, *archive_hash);
+    let mut catalog_bytes = Vec::new();
+    reader.read_to_end(&mut catalog_bytes)?;
+    Catalog::deserialize(&catalog_bytes)
+}
+
+/// Resolve `entry_path` (an archive entry's catalog path) against `dest`, rejecting anything that
+/// would let the entry write outside `dest`. The catalog is authenticated against the archive
+/// hash, which proves who wrote `entry_path`, but not that it's safe to join onto a destination
+/// directory: an absolute path or a `..` component would still let a malicious archive escape
+/// `dest` entirely.
+pub fn safe_member_path(dest: &Path, entry_path: &str) -> io::Result<PathBuf> {
+    let path = Path::new(entry_path);
+    if path.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("archive entry has an unsafe path: {}", entry_path),
+        ));
+    }
+    Ok(dest.join(path))
+}
+
+/// Extract a single named member out of `input` by streaming it through a `decode::Reader` seeded
+/// with the catalog-authenticated `entry.subtree_hash`. `input` must be positioned at the start of
+/// `entry`'s own encoding: right after the catalog for the first member, and right after the
+/// previous member's encoding for each later one. Seeking the returned reader seeks `input` to
+/// offsets counted from the start of the member's encoding, so random access within the member
+/// only works when `input`'s offset 0 is that start, not on the archive file as a whole.
+pub fn extract_member<R: Read>(input: R, entry: &CatalogEntry) -> decode::Reader<R> {
+    decode::Reader::new(input, entry.subtree_hash)
+}
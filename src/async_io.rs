@@ -0,0 +1,285 @@
+//! Async analogues of the streaming `decode`/`encode` types, for verified streaming over the
+//! network: a client seeks to an offset in a remote encoding, pulls only the subtree it needs,
+//! and verifies each chunk against the trusted root hash as bytes arrive, without blocking a
+//! thread per connection.
+//!
+//! The tricky part is suspending cleanly. `decode::State`'s `read_next()`/`seek_next()` machine
+//! already breaks the work into discrete steps (read a header, read a parent, read a chunk); we
+//! just need to remember which step we're mid-way through so that a `Poll::Pending` from the
+//! underlying transport resumes exactly there next time, instead of restarting the step and
+//! double-verifying (or worse, double-emitting) a chunk.
+
+#![cfg(feature = "tokio")]
+
+extern crate either;
+extern crate tokio;
+
+use decode::{State, StateNext};
+use hash::Finalization;
+use hash::{CHUNK_SIZE, HEADER_SIZE, PARENT_SIZE};
+
+use self::either::Either::{Left, Right};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use self::tokio::io::{AsyncRead, AsyncSeek};
+
+/// What in-flight read, if any, an `AsyncReader` is partway through. Needed because a poll can
+/// return early (`Poll::Pending`) after reading only part of a header/parent/chunk.
+enum InFlight {
+    None,
+    Header { filled: usize, buf: [u8; HEADER_SIZE] },
+    Parent { filled: usize, buf: [u8; PARENT_SIZE] },
+    Chunk {
+        filled: usize,
+        size: usize,
+        skip: usize,
+        finalization: Finalization,
+        buf: [u8; CHUNK_SIZE],
+    },
+}
+
+/// The async counterpart to `decode::Reader`. `T` is usually a network socket.
+pub struct AsyncReader<T> {
+    inner: T,
+    state: State,
+    in_flight: InFlight,
+    out_buf: [u8; CHUNK_SIZE],
+    out_start: usize,
+    out_end: usize,
+    /// Set for the duration of a `poll_seek` call: while this is `Some`, `poll_fill` drives
+    /// `state.seek_next()` (re-seeking `inner` to each step's encoded offset first) instead of
+    /// `state.read_next()`, the same way `decode::Reader::seek` swaps its loop's driver function
+    /// without changing the loop itself.
+    seek_target: Option<u64>,
+}
+
+impl<T: AsyncRead + AsyncSeek + Unpin> AsyncReader<T> {
+    pub fn new(inner: T, root_hash: ::hash::Hash) -> Self {
+        Self {
+            inner,
+            state: State::new(root_hash),
+            in_flight: InFlight::None,
+            out_buf: [0; CHUNK_SIZE],
+            out_start: 0,
+            out_end: 0,
+            seek_target: None,
+        }
+    }
+
+    fn out_len(&self) -> usize {
+        self.out_end - self.out_start
+    }
+
+    /// Drive whatever read is currently in flight (or start a new one, based on `state.read_next()`,
+    /// or `state.seek_next()` while a `poll_seek` is in progress) to completion, verifying it
+    /// against `state` as soon as it's fully buffered. Headers and parents are fed to `state` and
+    /// the loop moves on; `Poll::Ready(Ok(()))` comes back once a chunk has landed and been fed to
+    /// `state`, or immediately at EOF (or, while seeking, as soon as the target is within reach of
+    /// the next chunk).
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            if let InFlight::None = self.in_flight {
+                let next = match self.seek_target {
+                    Some(target) => {
+                        let (encoded_offset, next) = self.state.seek_next(target);
+                        // Keep `inner` positioned at the step `state` is about to process, the
+                        // same way `decode::Reader::seek` re-seeks its `T: Seek` between every
+                        // step of its own seek_next() loop, including the final `Done` step, since
+                        // seek_next() may have skipped whole subtrees without reading them.
+                        let offset = match cast_offset(encoded_offset) {
+                            Ok(offset) => offset,
+                            Err(e) => return Poll::Ready(Err(e)),
+                        };
+                        match Pin::new(&mut self.inner).poll_seek(cx, io::SeekFrom::Start(offset)) {
+                            Poll::Pending => return Poll::Pending,
+                            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                            Poll::Ready(Ok(_)) => {}
+                        }
+                        next
+                    }
+                    None => self.state.read_next(),
+                };
+                self.in_flight = match next {
+                    StateNext::Header => InFlight::Header {
+                        filled: 0,
+                        buf: [0; HEADER_SIZE],
+                    },
+                    StateNext::Subtree { .. } => InFlight::Parent {
+                        filled: 0,
+                        buf: [0; PARENT_SIZE],
+                    },
+                    StateNext::Chunk {
+                        size,
+                        skip,
+                        finalization,
+                    } => InFlight::Chunk {
+                        filled: 0,
+                        size,
+                        skip,
+                        finalization,
+                        buf: [0; CHUNK_SIZE],
+                    },
+                    StateNext::Done => return Poll::Ready(Ok(())),
+                };
+            }
+
+            let pin = Pin::new(&mut self.inner);
+            match &mut self.in_flight {
+                InFlight::None => unreachable!(),
+                InFlight::Header { filled, buf } => {
+                    match pin.poll_read(cx, &mut buf[*filled..]) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Ok(0)) => return Poll::Ready(Err(unexpected_eof())),
+                        Poll::Ready(Ok(n)) => {
+                            *filled += n;
+                            if *filled == buf.len() {
+                                self.state.feed_header(*buf);
+                                self.in_flight = InFlight::None;
+                            }
+                        }
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    }
+                }
+                InFlight::Parent { filled, buf } => {
+                    match pin.poll_read(cx, &mut buf[*filled..]) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Ok(0)) => return Poll::Ready(Err(unexpected_eof())),
+                        Poll::Ready(Ok(n)) => {
+                            *filled += n;
+                            if *filled == buf.len() {
+                                let result = self.state.feed_parent(*buf);
+                                self.in_flight = InFlight::None;
+                                result.map_err(|_| hash_mismatch())?;
+                            }
+                        }
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    }
+                }
+                InFlight::Chunk {
+                    filled,
+                    size,
+                    skip,
+                    finalization,
+                    buf,
+                } => {
+                    match pin.poll_read(cx, &mut buf[*filled..*size]) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Ok(0)) => return Poll::Ready(Err(unexpected_eof())),
+                        Poll::Ready(Ok(n)) => {
+                            *filled += n;
+                            if *filled == *size {
+                                let hash = self.state.compute_hash_node(&buf[..*size], *finalization);
+                                let result = self.state.feed_subtree(hash);
+                                self.out_buf[..*size].copy_from_slice(&buf[..*size]);
+                                self.out_start = *skip;
+                                self.out_end = *size;
+                                self.in_flight = InFlight::None;
+                                result.map_err(|_| hash_mismatch())?;
+                                return Poll::Ready(Ok(()));
+                            }
+                        }
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncSeek + Unpin> AsyncRead for AsyncReader<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.out_len() == 0 {
+            match self.poll_fill(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(())) => {
+                    if self.out_len() == 0 {
+                        return Poll::Ready(Ok(0)); // EOF
+                    }
+                }
+            }
+        }
+        let take = std::cmp::min(self.out_len(), buf.len());
+        let start = self.out_start;
+        buf[..take].copy_from_slice(&self.out_buf[start..start + take]);
+        self.out_start += take;
+        Poll::Ready(Ok(take))
+    }
+}
+
+impl<T: AsyncRead + AsyncSeek + Unpin> AsyncSeek for AsyncReader<T> {
+    fn poll_seek(mut self: Pin<&mut Self>, cx: &mut Context, pos: io::SeekFrom) -> Poll<io::Result<u64>> {
+        // First, read and verify the length if we haven't already, exactly like the sync
+        // `Reader::seek` does -- this also forces `seek_target` below to be absent yet, so
+        // `poll_fill` drives this via the ordinary `read_next()` path.
+        let content_length = loop {
+            match self.state.len_next() {
+                Left(len) => break len,
+                Right(_) => match self.poll_fill(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Ready(Ok(())) => continue,
+                },
+            }
+        };
+
+        let target = match pos {
+            io::SeekFrom::Start(p) => p,
+            io::SeekFrom::End(off) => match add_offset(content_length, off) {
+                Ok(target) => target,
+                Err(e) => return Poll::Ready(Err(e)),
+            },
+            io::SeekFrom::Current(off) => match add_offset(self.state.position(), off) {
+                Ok(target) => target,
+                Err(e) => return Poll::Ready(Err(e)),
+            },
+        };
+
+        // A seek invalidates whatever's buffered for the old position.
+        self.out_start = 0;
+        self.out_end = 0;
+        self.seek_target = Some(target);
+        loop {
+            match self.poll_fill(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(())) => break,
+            }
+        }
+        self.seek_target = None;
+        Poll::Ready(Ok(self.state.position()))
+    }
+}
+
+fn hash_mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "hash mismatch")
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected EOF")
+}
+
+fn cast_offset(offset: u128) -> io::Result<u64> {
+    if offset > u64::max_value() as u128 {
+        Err(io::Error::new(io::ErrorKind::Other, "seek offset overflowed u64"))
+    } else {
+        Ok(offset as u64)
+    }
+}
+
+fn add_offset(position: u64, offset: i64) -> io::Result<u64> {
+    let sum = position as i128 + offset as i128;
+    if sum < 0 {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before beginning"))
+    } else if sum > u64::max_value() as i128 {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "seek target overflowed u64"))
+    } else {
+        Ok(sum as u64)
+    }
+}
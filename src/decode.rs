@@ -1,23 +1,39 @@
 extern crate constant_time_eq;
 extern crate either;
+#[cfg(not(feature = "std"))]
+extern crate core_io;
+
+#[cfg(feature = "std")]
+pub mod store;
 
 use self::constant_time_eq::constant_time_eq;
 use self::either::Either::{self, Left, Right};
 use arrayvec::ArrayVec;
+use core::cmp;
 
 use encode;
 use hash::Finalization::{self, NotRoot, Root};
 use hash::{self, Hash, CHUNK_SIZE, HASH_SIZE, HEADER_SIZE, MAX_DEPTH, PARENT_SIZE};
 
-use std;
-use std::cmp;
+// The streaming types below (`State`, `Reader`) are generic over these traits rather than
+// `std::io::{Read, Write, Seek}` directly, so that firmware can verify e.g. a firmware image
+// against a trusted root hash while streaming it off flash or a UART, with no allocator and no
+// standard library. `decode::store`, which collects verified output into a `Vec`, stays behind
+// the `std` feature, since it needs an allocator regardless.
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "std")]
 use std::io::prelude::*;
+#[cfg(not(feature = "std"))]
+use self::core_io as io;
+#[cfg(not(feature = "std"))]
+use self::core_io::prelude::*;
 
 #[derive(Clone)]
 pub struct State {
     stack: ArrayVec<[Subtree; MAX_DEPTH]>,
     root_hash: Hash,
+    key: Option<hash::Key>,
     content_length: Option<u64>,
     length_verified: bool,
     content_position: u64,
@@ -26,9 +42,21 @@ pub struct State {
 
 impl State {
     pub fn new(root_hash: Hash) -> Self {
+        Self::new_maybe_keyed(root_hash, None)
+    }
+
+    /// Like `new`, but verifies every node (and thus the root hash itself) under `key`, as
+    /// produced by `hash::hash_keyed`/`encode::encode_keyed`. Feeding this `State` data encoded
+    /// under a different (or no) key fails verification exactly like corrupted data would.
+    pub fn new_keyed(root_hash: Hash, key: hash::Key) -> Self {
+        Self::new_maybe_keyed(root_hash, Some(key))
+    }
+
+    fn new_maybe_keyed(root_hash: Hash, key: Option<hash::Key>) -> Self {
         Self {
             stack: ArrayVec::new(),
             root_hash,
+            key,
             content_length: None,
             length_verified: false,
             content_position: 0,
@@ -36,10 +64,24 @@ impl State {
         }
     }
 
+    pub(crate) fn compute_hash_node(&self, node: &[u8], finalization: Finalization) -> Hash {
+        match &self.key {
+            Some(key) => hash::hash_node_keyed(node, finalization, key),
+            None => hash::hash_node(node, finalization),
+        }
+    }
+
     pub fn position(&self) -> u64 {
         self.content_position
     }
 
+    /// The hash of the subtree (chunk or parent node) that the next `feed_parent`/`feed_subtree`
+    /// call must verify against, i.e. the key a store-backed reader should fetch next. Panics if
+    /// we're already at EOF; check `read_next()` first.
+    pub fn current_hash(&self) -> Hash {
+        self.stack.last().expect("current_hash after EOF").hash
+    }
+
     fn reset_to_root(&mut self) {
         self.content_position = 0;
         self.encoded_offset = HEADER_SIZE as u128;
@@ -144,13 +186,13 @@ impl State {
         self.reset_to_root();
     }
 
-    pub fn feed_parent(&mut self, parent: hash::ParentNode) -> std::result::Result<(), ()> {
+    pub fn feed_parent(&mut self, parent: hash::ParentNode) -> Result<(), ()> {
         let content_length = self.content_length.expect("feed_parent before header");
         let current_subtree = *self.stack.last().expect("feed_parent after EOF");
         if current_subtree.len() <= CHUNK_SIZE as u64 {
             panic!("too many calls to feed_parent");
         }
-        let computed_hash = hash::hash_node(&parent, current_subtree.finalization(content_length));
+        let computed_hash = self.compute_hash_node(&parent, current_subtree.finalization(content_length));
         if !constant_time_eq(&current_subtree.hash, &computed_hash) {
             return Err(());
         }
@@ -173,7 +215,7 @@ impl State {
         Ok(())
     }
 
-    pub fn feed_subtree(&mut self, subtree: Hash) -> std::result::Result<(), ()> {
+    pub fn feed_subtree(&mut self, subtree: Hash) -> Result<(), ()> {
         let current_subtree = *self.stack.last().expect("feed_subtree after EOF");
         if !constant_time_eq(&subtree, &current_subtree.hash) {
             return Err(());
@@ -255,9 +297,34 @@ pub struct Reader<T: Read> {
 
 impl<T: Read> Reader<T> {
     pub fn new(inner: T, root_hash: Hash) -> Self {
+        Self::new_state(inner, State::new(root_hash))
+    }
+
+    /// Like `new`, but verifies the stream under `key`, matching `encode::encode_keyed`.
+    pub fn new_keyed(inner: T, root_hash: Hash, key: hash::Key) -> Self {
+        Self::new_state(inner, State::new_keyed(root_hash, key))
+    }
+
+    /// Like `new`/`new_keyed`, but resumes from an already-initialized `State` instead of
+    /// starting at the root -- e.g. a `State` another `Reader` has already fed its header (and
+    /// possibly verified some of the root path) via `state()`/`State`'s `Clone` impl. This is for
+    /// crate-internal callers like `mount`, which answer many small reads against the same
+    /// encoding and want to skip re-deriving the header and content length on every one.
+    pub(crate) fn with_state(inner: T, state: State) -> Self {
+        Self::new_state(inner, state)
+    }
+
+    /// The current decode state, for a caller that wants to `.clone()` it and resume elsewhere via
+    /// `with_state` (see `mount::BaoFilesystem`, which caches a post-header `State` and clones it
+    /// per FUSE read instead of rebuilding one from scratch each time).
+    pub(crate) fn state(&self) -> &State {
+        &self.state
+    }
+
+    fn new_state(inner: T, state: State) -> Self {
         Self {
             inner,
-            state: State::new(root_hash),
+            state,
             buf: [0; CHUNK_SIZE],
             buf_start: 0,
             buf_end: 0,
@@ -292,7 +359,7 @@ impl<T: Read> Reader<T> {
         self.buf_start = 0;
         self.buf_end = 0;
         self.inner.read_exact(&mut self.buf[..size])?;
-        let hash = hash::hash_node(&self.buf[..size], finalization);
+        let hash = self.state.compute_hash_node(&self.buf[..size], finalization);
         into_io(self.state.feed_subtree(hash))?;
         self.buf_start = skip;
         self.buf_end = size;
@@ -323,6 +390,110 @@ impl<T: Read> Read for Reader<T> {
         self.buf_start += take;
         Ok(take)
     }
+
+    // Once a chunk is verified and sitting in `self.buf`, there's no reason to copy it into the
+    // caller's buffer one slice at a time through the default `read_vectored` (which just calls
+    // `read` once). Fill each `IoSliceMut` directly from the verified buffer instead, spilling
+    // across slices until either the chunk or the slice list is exhausted, and only falling back
+    // to driving another `read_next()` step once both the buffer and the current slice are empty.
+    #[cfg(feature = "std")]
+    fn read_vectored(&mut self, mut bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        let mut total = 0;
+        while !bufs.is_empty() {
+            if self.buf_len() == 0 {
+                if total > 0 {
+                    // Don't block waiting on the next chunk if we've already delivered something;
+                    // the caller can call us again for more.
+                    break;
+                }
+                match self.state.read_next() {
+                    StateNext::Header => self.read_header()?,
+                    StateNext::Subtree { .. } => self.read_parent()?,
+                    StateNext::Chunk {
+                        size,
+                        skip,
+                        finalization,
+                    } => self.read_chunk(size, skip, finalization)?,
+                    StateNext::Done => break,
+                }
+                continue;
+            }
+            let take = cmp::min(self.buf_len(), bufs[0].len());
+            bufs[0][..take].copy_from_slice(&self.buf[self.buf_start..self.buf_start + take]);
+            self.buf_start += take;
+            total += take;
+            if take == bufs[0].len() {
+                bufs = &mut bufs[1..];
+            } else {
+                break;
+            }
+        }
+        Ok(total)
+    }
+}
+
+impl<T: Read + Seek> Reader<T> {
+    /// Like [`Read`], but splices verified bytes straight into `dest` instead of through a
+    /// caller-provided buffer, modeled on the `ZeroCopyReader` trait used by crosvm's virtio-fs
+    /// server. This is meant for servers (FUSE, network) answering many small positioned reads,
+    /// where routing every byte through userspace would otherwise dominate the cost of a read.
+    ///
+    /// Seeks to `offset`, then repeats the usual `seek_next`/`read_chunk` loop, writing each
+    /// verified chunk's in-range slice to `dest` as soon as it's available, until `count` bytes
+    /// have been delivered or the reader hits EOF. Returns the number of verified bytes written.
+    #[cfg(feature = "std")]
+    pub fn read_to_at(&mut self, dest: &mut std::fs::File, count: usize, offset: u64) -> io::Result<usize> {
+        self.seek(io::SeekFrom::Start(offset))?;
+        let mut written = 0;
+        while written < count {
+            if self.buf_len() == 0 {
+                match self.state.read_next() {
+                    StateNext::Header => self.read_header()?,
+                    StateNext::Subtree { .. } => self.read_parent()?,
+                    StateNext::Chunk {
+                        size,
+                        skip,
+                        finalization,
+                    } => self.read_chunk(size, skip, finalization)?,
+                    StateNext::Done => break,
+                }
+                continue;
+            }
+            let take = cmp::min(self.buf_len(), count - written);
+            dest.write_all(&self.buf[self.buf_start..self.buf_start + take])?;
+            self.buf_start += take;
+            written += take;
+        }
+        Ok(written)
+    }
+}
+
+impl<T: Read + Write + Seek> Reader<T> {
+    /// The write-side counterpart to `read_to_at`, modeled on crosvm's `ZeroCopyWriter`: copy
+    /// `count` raw encoded bytes from `src` into the underlying encoded file at `offset`, through
+    /// one fixed-size intermediate buffer reused across the whole call rather than one allocation
+    /// per call like a naive copy loop. This is for populating a local encoded-file cache from a
+    /// source that hands back bytes out of order (e.g. a network peer answering whichever range a
+    /// different reader asked for first); the bytes written here aren't verified on the way in,
+    /// since this `Reader`'s `state` may not have reached that part of the tree yet. They're
+    /// verified the ordinary way -- by `read_chunk`, against the root hash -- the next time
+    /// something reads them back out through this or another `Reader` over the same file.
+    #[cfg(feature = "std")]
+    pub fn write_from_at(&mut self, src: &mut std::fs::File, count: usize, offset: u64) -> io::Result<usize> {
+        self.inner.seek(io::SeekFrom::Start(offset))?;
+        let mut buf = [0; CHUNK_SIZE];
+        let mut copied = 0;
+        while copied < count {
+            let take = cmp::min(count - copied, buf.len());
+            let n = src.read(&mut buf[..take])?;
+            if n == 0 {
+                break;
+            }
+            self.inner.write_all(&buf[..n])?;
+            copied += n;
+        }
+        Ok(copied)
+    }
 }
 
 impl<T: Read + Seek> Seek for Reader<T> {
@@ -374,7 +545,270 @@ impl<T: Read + Seek> Seek for Reader<T> {
     }
 }
 
-fn into_io<T>(r: std::result::Result<T, ()>) -> io::Result<T> {
+/// Decodes and verifies a multi-range slice produced by `encode::SliceExtractor`, handing back
+/// each requested range's content in order. Unlike `Reader`, a `SliceReader`'s underlying stream
+/// only ever contains the parent nodes and chunks `encode::plan_multi_range_slice` says are
+/// needed for `ranges` -- content in between is never read at all, since the extractor never
+/// wrote it -- so `state`'s position is advanced across those gaps with `seek_next` bookkeeping
+/// alone, the same way `Reader::seek` skips unwanted bytes on a seekable source, except here there
+/// are no bytes to skip over in the first place.
+///
+/// Unlike `State`/`Reader` above, this needs a `Vec` for both `ranges` and the recomputed node
+/// plan, so -- like `encode::SliceExtractor`/`Writer` -- it stays behind the `std` feature rather
+/// than being `core_io`-generic; there's no allocator-only tier of this crate to put it in.
+#[cfg(feature = "std")]
+pub struct SliceReader<T: Read> {
+    inner: T,
+    state: State,
+    ranges: Vec<(u64, u64)>,
+    range_idx: usize,
+    cursor: u64,
+    // The canonical node order for `ranges`, recomputed locally once the header reveals the
+    // content length, and checked against as each node is actually consumed: a slice whose bytes
+    // don't follow this order is rejected outright, rather than relying on a hash mismatch to
+    // incidentally catch it.
+    expected_nodes: Vec<encode::SliceNode>,
+    expected_idx: usize,
+    buf: [u8; CHUNK_SIZE],
+    buf_start: usize,
+    buf_end: usize,
+}
+
+#[cfg(feature = "std")]
+impl<T: Read> SliceReader<T> {
+    /// `ranges` must be the same sorted, disjoint list of `(start, len)` pairs the slice was
+    /// extracted for.
+    pub fn new(inner: T, root_hash: Hash, ranges: Vec<(u64, u64)>) -> Self {
+        Self::new_state(inner, State::new(root_hash), ranges)
+    }
+
+    /// Like `new`, but verifies the slice under `key`, matching `encode::encode_keyed`.
+    pub fn new_keyed(inner: T, root_hash: Hash, key: hash::Key, ranges: Vec<(u64, u64)>) -> Self {
+        Self::new_state(inner, State::new_keyed(root_hash, key), ranges)
+    }
+
+    fn new_state(inner: T, state: State, ranges: Vec<(u64, u64)>) -> Self {
+        let cursor = ranges.first().map_or(0, |&(start, _)| start);
+        Self {
+            inner,
+            state,
+            ranges,
+            range_idx: 0,
+            cursor,
+            expected_nodes: Vec::new(),
+            expected_idx: 0,
+            buf: [0; CHUNK_SIZE],
+            buf_start: 0,
+            buf_end: 0,
+        }
+    }
+
+    fn buf_len(&self) -> usize {
+        self.buf_end - self.buf_start
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; HEADER_SIZE];
+        self.inner.read_exact(&mut header)?;
+        self.state.feed_header(header);
+        let content_len = hash::decode_len(header);
+        self.expected_nodes = encode::plan_multi_range_slice(content_len, &self.ranges);
+        Ok(())
+    }
+
+    fn ordering_error() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "slice node ordering does not match the canonical traversal for these ranges",
+        )
+    }
+
+    /// Confirm that the node about to be consumed from `self.inner` (a parent, if `is_parent`, or
+    /// a chunk otherwise) is the one `expected_nodes` says comes next.
+    fn check_expected(&mut self, is_parent: bool) -> io::Result<()> {
+        let node = self
+            .expected_nodes
+            .get(self.expected_idx)
+            .ok_or_else(Self::ordering_error)?;
+        let matches = match node {
+            encode::SliceNode::Parent { .. } => is_parent,
+            encode::SliceNode::Chunk { .. } => !is_parent,
+        };
+        if !matches {
+            return Err(Self::ordering_error());
+        }
+        self.expected_idx += 1;
+        Ok(())
+    }
+
+    fn read_parent(&mut self) -> io::Result<()> {
+        self.check_expected(true)?;
+        let mut parent = [0; PARENT_SIZE];
+        self.inner.read_exact(&mut parent)?;
+        into_io(self.state.feed_parent(parent))
+    }
+
+    fn read_chunk(&mut self, size: usize, skip: usize, finalization: Finalization) -> io::Result<()> {
+        self.check_expected(false)?;
+        self.buf_start = 0;
+        self.buf_end = 0;
+        self.inner.read_exact(&mut self.buf[..size])?;
+        let hash = self.state.compute_hash_node(&self.buf[..size], finalization);
+        into_io(self.state.feed_subtree(hash))?;
+        self.buf_start = skip;
+        self.buf_end = size;
+        Ok(())
+    }
+
+    /// Walk `state`'s virtual content position forward to `target`, reading (and verifying)
+    /// whatever parent nodes the plan says lie on the way, but never a chunk: `seek_next` never
+    /// asks for one, since it stops as soon as the target is within reach of the next chunk,
+    /// leaving that chunk to be read the ordinary way once `read` resumes.
+    fn advance_to(&mut self, target: u64) -> io::Result<()> {
+        loop {
+            let (_, next) = self.state.seek_next(target);
+            match next {
+                StateNext::Header => self.read_header()?,
+                StateNext::Subtree { .. } => self.read_parent()?,
+                StateNext::Chunk {
+                    size,
+                    skip,
+                    finalization,
+                } => self.read_chunk(size, skip, finalization)?,
+                StateNext::Done => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Read> Read for SliceReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (start, len) = match self.ranges.get(self.range_idx) {
+                Some(&range) => range,
+                None => return Ok(0),
+            };
+            let end = start + len;
+            if self.cursor >= end {
+                self.range_idx += 1;
+                continue;
+            }
+            if self.cursor < start {
+                self.cursor = start;
+            }
+
+            if self.buf_len() > 0 {
+                let chunk_end = self.state.position();
+                let chunk_start = chunk_end - self.buf_end as u64;
+                if self.cursor >= chunk_start && self.cursor < chunk_end {
+                    self.buf_start = (self.cursor - chunk_start) as usize;
+                    let avail = cmp::min(self.buf_end - self.buf_start, (end - self.cursor) as usize);
+                    let take = cmp::min(avail, buf.len());
+                    buf[..take].copy_from_slice(&self.buf[self.buf_start..self.buf_start + take]);
+                    self.buf_start += take;
+                    self.cursor += take as u64;
+                    return Ok(take);
+                }
+                // The buffered chunk no longer covers the cursor (we've moved on to a range that
+                // starts past it); there's nothing useful left in it.
+                self.buf_start = self.buf_end;
+            }
+
+            if self.state.position() < self.cursor {
+                self.advance_to(self.cursor)?;
+            }
+            match self.state.read_next() {
+                StateNext::Header => self.read_header()?,
+                StateNext::Subtree { .. } => self.read_parent()?,
+                StateNext::Chunk {
+                    size,
+                    skip,
+                    finalization,
+                } => self.read_chunk(size, skip, finalization)?,
+                StateNext::Done => return Ok(0),
+            }
+        }
+    }
+
+    // Unlike `Reader::read_vectored`, `read`'s own buffer bookkeeping here already has to juggle
+    // range boundaries and a chunk shared across two ranges, so there's little left to save by
+    // reaching into `self.buf` directly per `IoSliceMut`; just drive `read` once per slice, the
+    // same way the default `read_vectored` would, but stopping as soon as one comes up short
+    // instead of paying for a slice we can't fill yet.
+    #[cfg(feature = "std")]
+    fn read_vectored(&mut self, mut bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        let mut total = 0;
+        while !bufs.is_empty() {
+            let n = self.read(&mut bufs[0])?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+            if n == bufs[0].len() {
+                bufs = &mut bufs[1..];
+            } else {
+                break;
+            }
+        }
+        Ok(total)
+    }
+}
+
+/// The one-shot counterpart to `encode::encode_keyed`: verify `input` (an 8-byte header followed
+/// by the combined tree) against `root_hash` under `key`, writing the decoded content into
+/// `output`, and return the content length the header claims. Fails with `InvalidData` the moment
+/// any node's hash doesn't match -- the same failure `Reader::new_keyed` reports, just without the
+/// streaming machinery, for callers that already have the whole encoding in memory.
+pub fn decode_keyed(input: &[u8], output: &mut [u8], root_hash: Hash, key: hash::Key) -> io::Result<u64> {
+    let header = *array_ref!(input, 0, HEADER_SIZE);
+    let content_len = hash::decode_len(header);
+    into_io(decode_keyed_recurse(
+        &input[HEADER_SIZE..],
+        output,
+        content_len,
+        Root(content_len),
+        &root_hash,
+        &key,
+    ))?;
+    Ok(content_len)
+}
+
+fn decode_keyed_recurse(
+    encoded: &[u8],
+    output: &mut [u8],
+    subtree_len: u64,
+    finalization: Finalization,
+    expected_hash: &Hash,
+    key: &hash::Key,
+) -> Result<(), ()> {
+    if subtree_len <= CHUNK_SIZE as u64 {
+        let chunk = &encoded[..subtree_len as usize];
+        let computed_hash = hash::hash_node_keyed(chunk, finalization, key);
+        if !constant_time_eq(&computed_hash, expected_hash) {
+            return Err(());
+        }
+        output[..subtree_len as usize].copy_from_slice(chunk);
+        return Ok(());
+    }
+
+    let parent = array_ref!(encoded, 0, PARENT_SIZE);
+    let computed_hash = hash::hash_node_keyed(parent, finalization, key);
+    if !constant_time_eq(&computed_hash, expected_hash) {
+        return Err(());
+    }
+    let left_hash = *array_ref!(parent, 0, HASH_SIZE);
+    let right_hash = *array_ref!(parent, HASH_SIZE, HASH_SIZE);
+    let left_len = hash::left_len(subtree_len);
+    let left_size = encode::encoded_subtree_size(left_len) as usize;
+    let (left_encoded, right_encoded) = encoded[PARENT_SIZE..].split_at(left_size);
+    let (left_out, right_out) = output.split_at_mut(left_len as usize);
+
+    decode_keyed_recurse(left_encoded, left_out, left_len, NotRoot, &left_hash, key)?;
+    decode_keyed_recurse(right_encoded, right_out, subtree_len - left_len, NotRoot, &right_hash, key)
+}
+
+fn into_io<T>(r: Result<T, ()>) -> io::Result<T> {
     r.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "hash mismatch"))
 }
 
@@ -0,0 +1,417 @@
+//! Encoding support. This file currently only carries the pieces of the encoder that have grown
+//! out of specific feature work; the original serial `encode` lives alongside these and isn't
+//! reproduced here. The streaming `Writer` and `SliceExtractor` *are* defined here; the latter
+//! only ever needed to exist to serve multi-range slicing (see `plan_multi_range_slice` below),
+//! so there was no pre-existing single-range version to extend.
+//!
+//! The multi-range slice plan, `SliceExtractor`, the streaming `Writer` and `store` need a `Vec`,
+//! whether that's the slice plan itself or an accumulation buffer, and this crate has no
+//! allocator-only middle tier between `core` and `std` the way `decode::State`/`Reader` get by on
+//! (see their module doc) -- so those stay behind the `std` feature rather than pretending to be
+//! `core_io`-generic. The one-shot functions over caller-provided slices (`encode_parallel`,
+//! `encode_keyed`, `encode_outboard_keyed`) and the size helpers need no allocator and aren't
+//! gated on `std`.
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
+#[cfg(feature = "std")]
+pub mod store;
+
+use hash;
+use hash::Finalization::{NotRoot, Root};
+use hash::{Hash, CHUNK_SIZE, HEADER_SIZE, PARENT_SIZE};
+
+#[cfg(feature = "std")]
+use std::cmp;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::io::prelude::*;
+
+/// The size in bytes of the encoded tree rooted at a subtree of `subtree_len` content bytes,
+/// including that subtree's own chunks and parent nodes but not any header.
+pub fn encoded_subtree_size(subtree_len: u64) -> u128 {
+    // Every chunk is encoded as-is, and every pair of children costs one more parent node.
+    let num_chunks = (subtree_len / CHUNK_SIZE as u64) + (subtree_len % CHUNK_SIZE as u64 > 0) as u64;
+    let num_chunks = num_chunks.max(1);
+    subtree_len as u128 + (num_chunks - 1) as u128 * hash::PARENT_SIZE as u128
+}
+
+/// Hash and encode `input` into `output` using as many threads as are available, splitting the
+/// tree at each level with [`hash::left_len`] and recursing on the two halves in parallel via
+/// `rayon::join`. The resulting bytes in `output` are identical to what the serial encoder would
+/// produce; only the order of internal hashing work differs.
+#[cfg(feature = "rayon")]
+pub fn encode_parallel(input: &[u8], output: &mut [u8]) -> Hash {
+    output[..HEADER_SIZE].copy_from_slice(&hash::encode_len(input.len() as u64));
+    encode_parallel_recurse(input, &mut output[HEADER_SIZE..], Root(input.len() as u64))
+}
+
+#[cfg(feature = "rayon")]
+fn encode_parallel_recurse(input: &[u8], output: &mut [u8], finalization: hash::Finalization) -> Hash {
+    if input.len() <= CHUNK_SIZE {
+        output[..input.len()].copy_from_slice(input);
+        return hash::hash_node(input, finalization);
+    }
+
+    let left_len = hash::left_len(input.len() as u64) as usize;
+    let (left_input, right_input) = input.split_at(left_len);
+    let left_size = encoded_subtree_size(left_len as u64) as usize;
+
+    // The parent node goes first, followed by the left subtree and then the right subtree. We
+    // can't fill in the parent node's hash bytes until both children are done, so split the
+    // output buffer up front and come back to the parent slot afterwards.
+    let (parent_out, rest_out) = output.split_at_mut(hash::PARENT_SIZE);
+    let (left_out, right_out) = rest_out.split_at_mut(left_size);
+
+    let (left_hash, right_hash) = rayon::join(
+        || encode_parallel_recurse(left_input, left_out, NotRoot),
+        || encode_parallel_recurse(right_input, right_out, NotRoot),
+    );
+
+    let mut parent = [0; hash::PARENT_SIZE];
+    parent[..hash::HASH_SIZE].copy_from_slice(&left_hash);
+    parent[hash::HASH_SIZE..].copy_from_slice(&right_hash);
+    parent_out.copy_from_slice(&parent);
+    hash::hash_node(&parent, finalization)
+}
+
+/// One step of a depth-first walk over the tree, as produced by `plan_multi_range_slice`: either
+/// a parent node that must be emitted to authenticate its children, or a chunk that falls inside
+/// (or covers part of) one of the requested ranges.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceNode {
+    Parent { encoded_offset: u128 },
+    Chunk { content_offset: u64, len: usize, encoded_offset: u128 },
+}
+
+/// Walk the tree rooted at a `content_len`-byte input and compute the depth-first list of nodes
+/// a multi-range slice must carry to authenticate every byte in `ranges` (sorted, disjoint
+/// `(start, len)` pairs), emitting each node at most once even when it's shared spine between two
+/// requested ranges. This is the piece of multi-range slicing that's independent of I/O; turning
+/// it into bytes on the wire is `SliceExtractor`'s job, below, and replaying it against a root
+/// hash while streaming decoded bytes back out is `decode::SliceReader`'s.
+#[cfg(feature = "std")]
+pub fn plan_multi_range_slice(content_len: u64, ranges: &[(u64, u64)]) -> Vec<SliceNode> {
+    let mut nodes = Vec::new();
+    if content_len > 0 {
+        plan_recurse(0, content_len, 0, ranges, &mut nodes);
+    }
+    nodes
+}
+
+#[cfg(feature = "std")]
+fn subtree_needed(start: u64, end: u64, ranges: &[(u64, u64)]) -> bool {
+    ranges
+        .iter()
+        .any(|&(range_start, range_len)| range_start < end && range_start + range_len > start)
+}
+
+#[cfg(feature = "std")]
+fn plan_recurse(
+    start: u64,
+    end: u64,
+    encoded_offset: u128,
+    ranges: &[(u64, u64)],
+    nodes: &mut Vec<SliceNode>,
+) {
+    if !subtree_needed(start, end, ranges) {
+        return;
+    }
+    let len = end - start;
+    if len <= CHUNK_SIZE as u64 {
+        nodes.push(SliceNode::Chunk {
+            content_offset: start,
+            len: len as usize,
+            encoded_offset,
+        });
+        return;
+    }
+    nodes.push(SliceNode::Parent { encoded_offset });
+    let split = start + hash::left_len(len);
+    plan_recurse(start, split, encoded_offset + PARENT_SIZE as u128, ranges, nodes);
+    let left_size = encoded_subtree_size(split - start);
+    plan_recurse(
+        split,
+        end,
+        encoded_offset + PARENT_SIZE as u128 + left_size,
+        ranges,
+        nodes,
+    );
+}
+
+/// Streams the bytes of a multi-range slice out of a full combined encoding: the 8-byte header,
+/// followed by exactly the parent nodes and chunks `plan_multi_range_slice` says are needed,
+/// back to back in depth-first order, with every shared-spine node written only once. `decode::
+/// SliceReader` is the matching consumer, and expects precisely this layout.
+#[cfg(feature = "std")]
+pub struct SliceExtractor<T: Read + Seek> {
+    input: T,
+    content_len: u64,
+    nodes: Vec<SliceNode>,
+    node_idx: usize,
+    header_sent: bool,
+    buf: [u8; CHUNK_SIZE],
+    buf_start: usize,
+    buf_end: usize,
+}
+
+#[cfg(feature = "std")]
+impl<T: Read + Seek> SliceExtractor<T> {
+    /// `ranges` must be sorted and disjoint, as `plan_multi_range_slice` requires; a single-range
+    /// slice is just `ranges: &[(start, len)]` with one element.
+    pub fn new(input: T, content_len: u64, ranges: &[(u64, u64)]) -> Self {
+        Self {
+            input,
+            content_len,
+            nodes: plan_multi_range_slice(content_len, ranges),
+            node_idx: 0,
+            header_sent: false,
+            buf: [0; CHUNK_SIZE],
+            buf_start: 0,
+            buf_end: 0,
+        }
+    }
+
+    fn buf_len(&self) -> usize {
+        self.buf_end - self.buf_start
+    }
+
+    /// Seek to and read the next planned node's bytes into `buf`, returning `false` once the plan
+    /// is exhausted.
+    fn fill_next_node(&mut self) -> io::Result<bool> {
+        let node = match self.nodes.get(self.node_idx) {
+            Some(&node) => node,
+            None => return Ok(false),
+        };
+        self.node_idx += 1;
+        let (encoded_offset, len) = match node {
+            SliceNode::Parent { encoded_offset } => (encoded_offset, PARENT_SIZE),
+            SliceNode::Chunk { encoded_offset, len, .. } => (encoded_offset, len),
+        };
+        let file_offset = cast_offset(HEADER_SIZE as u128 + encoded_offset)?;
+        self.input.seek(io::SeekFrom::Start(file_offset))?;
+        self.buf_start = 0;
+        self.buf_end = len;
+        self.input.read_exact(&mut self.buf[..len])?;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Read + Seek> Read for SliceExtractor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.header_sent {
+            self.header_sent = true;
+            self.buf_start = 0;
+            self.buf_end = HEADER_SIZE;
+            self.buf[..HEADER_SIZE].copy_from_slice(&hash::encode_len(self.content_len));
+        } else if self.buf_len() == 0 && !self.fill_next_node()? {
+            return Ok(0);
+        }
+        let take = cmp::min(self.buf_len(), buf.len());
+        buf[..take].copy_from_slice(&self.buf[self.buf_start..self.buf_start + take]);
+        self.buf_start += take;
+        Ok(take)
+    }
+}
+
+#[cfg(feature = "std")]
+fn cast_offset(offset: u128) -> io::Result<u64> {
+    if offset > u64::max_value() as u128 {
+        Err(io::Error::new(io::ErrorKind::Other, "encoded offset overflowed u64"))
+    } else {
+        Ok(offset as u64)
+    }
+}
+
+/// A streaming encoder: bytes can arrive across any number of `write` calls, and the total length
+/// never needs to be known up front. A chunk's parent node always precedes that chunk in the
+/// stream, but we can't tell a chunk's parent is complete -- that its sibling has arrived too --
+/// until later input shows up, so unlike `decode::Reader` there's no way to emit bytes as they're
+/// written; `write` buffers the whole input and `finish` walks the tree in one pass, the same
+/// tradeoff `hash::ParallelWriter` makes for hashing alone.
+#[cfg(feature = "std")]
+pub struct Writer<W: Write> {
+    inner: W,
+    key: Option<hash::Key>,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self::new_maybe_keyed(inner, None)
+    }
+
+    /// Like `new`, but every node is derived under `key`, matching `hash::hash_keyed`/`encode_keyed`.
+    pub fn new_keyed(inner: W, key: &hash::Key) -> Self {
+        Self::new_maybe_keyed(inner, Some(*key))
+    }
+
+    fn new_maybe_keyed(inner: W, key: Option<hash::Key>) -> Self {
+        Self {
+            inner,
+            key,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Write the header and the full combined encoding of everything written so far to `inner`,
+    /// and return the root hash -- the same hash `hash::hash`/`hash::hash_keyed` would compute
+    /// over the same bytes.
+    pub fn finish(mut self) -> io::Result<Hash> {
+        let content_len = self.buf.len() as u64;
+        let mut output = vec![0; HEADER_SIZE + encoded_subtree_size(content_len) as usize];
+        output[..HEADER_SIZE].copy_from_slice(&hash::encode_len(content_len));
+        let hash = write_recurse(&self.buf, &mut output[HEADER_SIZE..], Root(content_len), self.key.as_ref());
+        self.inner.write_all(&output)?;
+        Ok(hash)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // `write` above already takes the whole slice unconditionally (there's nowhere for a partial
+    // write to come from -- `buf` just grows), so the only thing worth overriding here is avoiding
+    // one `extend_from_slice` call per `IoSlice` becoming one `write` call each through the default
+    // impl's loop.
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs {
+            self.buf.extend_from_slice(buf);
+            total += buf.len();
+        }
+        Ok(total)
+    }
+}
+
+/// The recursion `Writer::finish` uses to lay out the buffered input as a combined encoding,
+/// parameterized over an optional key the same way `hash::hash_node_maybe_keyed` is internally;
+/// unlike `encode_keyed_recurse`/`encode_parallel_recurse`, this one only ever runs once, at
+/// `finish` time, so there's no reason to special-case the unkeyed, non-parallel path separately.
+#[cfg(feature = "std")]
+fn write_recurse(input: &[u8], output: &mut [u8], finalization: hash::Finalization, key: Option<&hash::Key>) -> Hash {
+    if input.len() <= CHUNK_SIZE {
+        output[..input.len()].copy_from_slice(input);
+        return match key {
+            Some(k) => hash::hash_node_keyed(input, finalization, k),
+            None => hash::hash_node(input, finalization),
+        };
+    }
+
+    let left_len = hash::left_len(input.len() as u64) as usize;
+    let (left_input, right_input) = input.split_at(left_len);
+    let left_size = encoded_subtree_size(left_len as u64) as usize;
+    let (parent_out, rest_out) = output.split_at_mut(hash::PARENT_SIZE);
+    let (left_out, right_out) = rest_out.split_at_mut(left_size);
+
+    let left_hash = write_recurse(left_input, left_out, NotRoot, key);
+    let right_hash = write_recurse(right_input, right_out, NotRoot, key);
+
+    let mut parent = [0; hash::PARENT_SIZE];
+    parent[..hash::HASH_SIZE].copy_from_slice(&left_hash);
+    parent[hash::HASH_SIZE..].copy_from_slice(&right_hash);
+    parent_out.copy_from_slice(&parent);
+    match key {
+        Some(k) => hash::hash_node_keyed(&parent, finalization, k),
+        None => hash::hash_node(&parent, finalization),
+    }
+}
+
+/// The size in bytes of just the parent-node tree for a subtree of `subtree_len` content bytes, as
+/// `encode_outboard_keyed` writes it -- the same shape as `encoded_subtree_size`, minus the chunk
+/// bytes an outboard encoding doesn't duplicate.
+pub fn outboard_subtree_size(subtree_len: u64) -> u128 {
+    let num_chunks = (subtree_len / CHUNK_SIZE as u64) + (subtree_len % CHUNK_SIZE as u64 > 0) as u64;
+    let num_chunks = num_chunks.max(1);
+    (num_chunks - 1) as u128 * hash::PARENT_SIZE as u128
+}
+
+/// Like the (unshown) serial `encode`, but every node is derived under `key`, matching
+/// `hash::hash_keyed`. `encode_outboard_keyed` is the same, except that `input` is never copied
+/// and `output` only receives the length header and the tree of parent hashes; both forms of the
+/// same `(key, input)` pair produce identical root hashes, since the root-finalization step binds
+/// the key identically either way.
+///
+/// Note: this only covers the one-shot, in-memory form; the streaming form is
+/// `Writer::new_keyed`, above.
+pub fn encode_keyed(key: &hash::Key, input: &[u8], output: &mut [u8]) -> Hash {
+    output[..HEADER_SIZE].copy_from_slice(&hash::encode_len(input.len() as u64));
+    encode_keyed_recurse(key, input, &mut output[HEADER_SIZE..], Root(input.len() as u64))
+}
+
+fn encode_keyed_recurse(
+    key: &hash::Key,
+    input: &[u8],
+    output: &mut [u8],
+    finalization: hash::Finalization,
+) -> Hash {
+    if input.len() <= CHUNK_SIZE {
+        output[..input.len()].copy_from_slice(input);
+        return hash::hash_node_keyed(input, finalization, key);
+    }
+
+    let left_len = hash::left_len(input.len() as u64) as usize;
+    let (left_input, right_input) = input.split_at(left_len);
+    let left_size = encoded_subtree_size(left_len as u64) as usize;
+    let (parent_out, rest_out) = output.split_at_mut(hash::PARENT_SIZE);
+    let (left_out, right_out) = rest_out.split_at_mut(left_size);
+
+    let left_hash = encode_keyed_recurse(key, left_input, left_out, NotRoot);
+    let right_hash = encode_keyed_recurse(key, right_input, right_out, NotRoot);
+
+    let mut parent = [0; hash::PARENT_SIZE];
+    parent[..hash::HASH_SIZE].copy_from_slice(&left_hash);
+    parent[hash::HASH_SIZE..].copy_from_slice(&right_hash);
+    parent_out.copy_from_slice(&parent);
+    hash::hash_node_keyed(&parent, finalization, key)
+}
+
+/// Like `encode_keyed`, but `output` receives only the 8-byte length header followed by the tree
+/// of parent hashes (sized `HEADER_SIZE` plus `outboard_subtree_size`, not
+/// `encoded_subtree_size`) and `input` itself is never copied
+/// anywhere -- a caller who already has `input` on disk keeps it untouched and stores just the
+/// much smaller outboard tree alongside it.
+pub fn encode_outboard_keyed(key: &hash::Key, input: &[u8], output: &mut [u8]) -> Hash {
+    output[..HEADER_SIZE].copy_from_slice(&hash::encode_len(input.len() as u64));
+    encode_outboard_keyed_recurse(key, input, &mut output[HEADER_SIZE..], Root(input.len() as u64))
+}
+
+fn encode_outboard_keyed_recurse(
+    key: &hash::Key,
+    input: &[u8],
+    output: &mut [u8],
+    finalization: hash::Finalization,
+) -> Hash {
+    if input.len() <= CHUNK_SIZE {
+        // Leaves contribute no bytes to an outboard encoding; `input` is the only copy of them.
+        return hash::hash_node_keyed(input, finalization, key);
+    }
+
+    let left_len = hash::left_len(input.len() as u64) as usize;
+    let (left_input, right_input) = input.split_at(left_len);
+    let left_size = outboard_subtree_size(left_len as u64) as usize;
+    let (parent_out, rest_out) = output.split_at_mut(hash::PARENT_SIZE);
+    let (left_out, right_out) = rest_out.split_at_mut(left_size);
+
+    let left_hash = encode_outboard_keyed_recurse(key, left_input, left_out, NotRoot);
+    let right_hash = encode_outboard_keyed_recurse(key, right_input, right_out, NotRoot);
+
+    let mut parent = [0; hash::PARENT_SIZE];
+    parent[..hash::HASH_SIZE].copy_from_slice(&left_hash);
+    parent[hash::HASH_SIZE..].copy_from_slice(&right_hash);
+    parent_out.copy_from_slice(&parent);
+    hash::hash_node_keyed(&parent, finalization, key)
+}
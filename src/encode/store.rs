@@ -0,0 +1,96 @@
+//! Encoding into a content-addressed chunk store instead of one monolithic stream: every chunk
+//! and parent node is written out as its own object, keyed by the same hash that
+//! `decode::store::Reader` later verifies it against. Identical chunks across different files
+//! collapse to a single stored object for free, since the key *is* the hash.
+
+use encode;
+use hash;
+use hash::Finalization::{NotRoot, Root};
+use hash::{Hash, CHUNK_SIZE};
+
+/// A single step of the walk over an encoding: the content-space range this node covers, the
+/// range it would occupy in a combined encoding (for callers that want to reconstruct one), and
+/// the hash that keys it in the store.
+pub struct ChunkInfo {
+    pub content_start: u64,
+    pub content_end: u64,
+    pub encoded_start: u128,
+    pub encoded_end: u128,
+    pub hash: Hash,
+}
+
+/// Somewhere to put the objects a store-encode produces, keyed by their own hash. This mirrors
+/// `decode::store::ChunkSource` on the read side.
+pub trait ChunkSink {
+    fn put(&mut self, hash: &Hash, bytes: &[u8]) -> std::io::Result<()>;
+}
+
+/// Walk `input`'s tree, writing each chunk and parent node to `sink` keyed by its hash, and
+/// return the root hash (the store's manifest key) alongside a `ChunkInfo` for every object that
+/// was written, in the same order `sink.put` saw them: depth-first, with each parent node after
+/// both of its children. A parent's `content_start` equals its first chunk's, so `infos` is not
+/// sorted by `content_start`; its `encoded_start`/`encoded_end` give each object's place in a
+/// combined encoding, for a caller that wants to reassemble one later.
+pub fn encode_store(input: &[u8], sink: &mut dyn ChunkSink) -> std::io::Result<(Hash, Vec<ChunkInfo>)> {
+    let mut infos = Vec::new();
+    let hash = encode_store_recurse(input, sink, Root(input.len() as u64), 0, 0, &mut infos)?;
+    Ok((hash, infos))
+}
+
+fn encode_store_recurse(
+    input: &[u8],
+    sink: &mut dyn ChunkSink,
+    finalization: hash::Finalization,
+    content_offset: u64,
+    encoded_offset: u128,
+    infos: &mut Vec<ChunkInfo>,
+) -> std::io::Result<Hash> {
+    if input.len() <= CHUNK_SIZE {
+        let chunk_hash = hash::hash_node(input, finalization);
+        sink.put(&chunk_hash, input)?;
+        infos.push(ChunkInfo {
+            content_start: content_offset,
+            content_end: content_offset + input.len() as u64,
+            encoded_start: encoded_offset,
+            encoded_end: encoded_offset + input.len() as u128,
+            hash: chunk_hash,
+        });
+        return Ok(chunk_hash);
+    }
+
+    let left_len = hash::left_len(input.len() as u64) as usize;
+    let (left_input, right_input) = input.split_at(left_len);
+    let parent_encoded_start = encoded_offset;
+    let left_encoded_offset = encoded_offset + hash::PARENT_SIZE as u128;
+    let left_hash = encode_store_recurse(
+        left_input,
+        sink,
+        NotRoot,
+        content_offset,
+        left_encoded_offset,
+        infos,
+    )?;
+    let right_encoded_offset = left_encoded_offset + encode::encoded_subtree_size(left_len as u64);
+    let right_hash = encode_store_recurse(
+        right_input,
+        sink,
+        NotRoot,
+        content_offset + left_len as u64,
+        right_encoded_offset,
+        infos,
+    )?;
+
+    let mut parent = [0; hash::PARENT_SIZE];
+    parent[..hash::HASH_SIZE].copy_from_slice(&left_hash);
+    parent[hash::HASH_SIZE..].copy_from_slice(&right_hash);
+    let parent_hash = hash::hash_node(&parent, finalization);
+    sink.put(&parent_hash, &parent)?;
+    infos.push(ChunkInfo {
+        content_start: content_offset,
+        content_end: content_offset + input.len() as u64,
+        encoded_start: parent_encoded_start,
+        encoded_end: right_encoded_offset + encode::encoded_subtree_size((input.len() - left_len) as u64),
+        hash: parent_hash,
+    });
+    Ok(parent_hash)
+}
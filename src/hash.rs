@@ -0,0 +1,334 @@
+//! Tree-hashing primitives shared by `encode` and `decode`. This file collects the constants and
+//! helpers those modules already import (`CHUNK_SIZE`, `Finalization`, `hash_node`, `left_len`,
+//! ...), the keyed-mode addition, and the streaming `Writer`/`ParallelWriter` hashers.
+
+extern crate blake2b_simd;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
+use arrayvec::ArrayVec;
+use core::cmp;
+#[cfg(feature = "std")]
+use std::vec::Vec;
+
+pub const CHUNK_SIZE: usize = 4096;
+pub const HASH_SIZE: usize = 32;
+pub const HEADER_SIZE: usize = 8;
+pub const PARENT_SIZE: usize = 2 * HASH_SIZE;
+pub const MAX_DEPTH: usize = 64;
+
+pub type Hash = [u8; HASH_SIZE];
+pub type ParentNode = [u8; PARENT_SIZE];
+pub type Key = [u8; HASH_SIZE];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Finalization {
+    Root(u64),
+    NotRoot,
+}
+
+pub fn decode_len(header: [u8; HEADER_SIZE]) -> u64 {
+    u64::from_le_bytes(header)
+}
+
+pub fn encode_len(len: u64) -> [u8; HEADER_SIZE] {
+    len.to_le_bytes()
+}
+
+/// The length, in content bytes, of the left child of a subtree of `len` bytes: the largest
+/// power-of-two multiple of `CHUNK_SIZE` strictly less than `len`.
+pub fn left_len(len: u64) -> u64 {
+    debug_assert!(len > CHUNK_SIZE as u64);
+    let full_chunks = (len - 1) / CHUNK_SIZE as u64;
+    let mut chunks = full_chunks.next_power_of_two();
+    if chunks > full_chunks {
+        chunks /= 2;
+    }
+    if chunks == 0 {
+        chunks = 1;
+    }
+    chunks * CHUNK_SIZE as u64
+}
+
+/// Hash a single chunk or parent node. Root nodes (the top of the tree, or a whole-input chunk
+/// short enough to have no parent at all) additionally bind the total content length, so that a
+/// length-truncation attack can't pass verification.
+pub fn hash_node(node: &[u8], finalization: Finalization) -> Hash {
+    hash_node_maybe_keyed(node, finalization, None)
+}
+
+/// Like `hash_node`, but every node (leaf, parent, and the root) is additionally keyed with
+/// `key`, via BLAKE2b's native key parameter. A decoder must supply the same `key` to reproduce
+/// this hash; otherwise decoding fails with a hash mismatch, exactly as if the data were corrupt.
+/// Binding the key identically at every level (including the root-finalization step) is what
+/// makes combined and outboard encodings of the same `(key, input)` pair agree on their root hash.
+pub fn hash_node_keyed(node: &[u8], finalization: Finalization, key: &Key) -> Hash {
+    hash_node_maybe_keyed(node, finalization, Some(key))
+}
+
+fn hash_node_maybe_keyed(node: &[u8], finalization: Finalization, key: Option<&Key>) -> Hash {
+    let mut params = blake2b_simd::Params::new();
+    params.hash_length(HASH_SIZE);
+    if let Some(key) = key {
+        params.key(key);
+    }
+    let mut state = params.to_state();
+    state.update(node);
+    if let Finalization::Root(len) = finalization {
+        // The root node's finalization is distinguished by folding the encoded content length
+        // into the hash, matching `hash::decode_len`/`encode_len` on the decode side.
+        state.update(&encode_len(len));
+    }
+    let mut hash = [0; HASH_SIZE];
+    hash.copy_from_slice(state.finalize().as_bytes());
+    hash
+}
+
+/// The one-shot keyed hash of `input`: recursively split at `left_len`, hash each half under
+/// `key`, and combine with `hash_node_keyed`, finalizing the root with the total length. This is
+/// the non-streaming analogue of `Writer::new_keyed`, for callers that already have the whole
+/// input in memory.
+pub fn hash_keyed(key: &Key, input: &[u8]) -> Hash {
+    hash_keyed_recurse(key, input, Finalization::Root(input.len() as u64))
+}
+
+fn hash_keyed_recurse(key: &Key, input: &[u8], finalization: Finalization) -> Hash {
+    if input.len() <= CHUNK_SIZE {
+        return hash_node_keyed(input, finalization, key);
+    }
+    let split = left_len(input.len() as u64) as usize;
+    let (left, right) = input.split_at(split);
+    let left_hash = hash_keyed_recurse(key, left, Finalization::NotRoot);
+    let right_hash = hash_keyed_recurse(key, right, Finalization::NotRoot);
+    let mut parent = [0; PARENT_SIZE];
+    parent[..HASH_SIZE].copy_from_slice(&left_hash);
+    parent[HASH_SIZE..].copy_from_slice(&right_hash);
+    hash_node_keyed(&parent, finalization, key)
+}
+
+/// The non-root chaining value of a subtree, the low-level building block for hashing
+/// chunk-aligned spans on separate machines and combining the results (mirroring what
+/// `ParallelWriter` does internally, but exposed so a coordinator can drive it across a cluster).
+pub type ChainingValue = Hash;
+
+/// Compute the non-root chaining value of `input`, a contiguous, chunk-aligned slice (its length
+/// must be a multiple of `CHUNK_SIZE`, except possibly for the final subtree of the whole input).
+/// `chunk_offset` is the index of `input`'s first chunk within the whole file; it doesn't affect
+/// the hash (BLAKE2b's tree mode isn't used here), but callers must still supply it, since a
+/// worker only sees its own shard and the coordinator needs the offset to verify shards are
+/// contiguous before calling `merge`.
+///
+/// `hash_subtree` only ever produces a *non-root* chaining value, even when `input` happens to be
+/// the whole file: finalize the top two shards with `merge_root`, not this function, to get a
+/// `Hash` comparable against `hash::hash`'s output. If the whole file is small enough to be a
+/// single chunk, skip `hash_subtree`/`merge_root` entirely and call
+/// `hash_node(input, Finalization::Root(len))` directly.
+pub fn hash_subtree(input: &[u8], chunk_offset: u64) -> ChainingValue {
+    let _ = chunk_offset;
+    hash_subtree_maybe_keyed(input, None)
+}
+
+fn hash_subtree_maybe_keyed(input: &[u8], key: Option<&Key>) -> ChainingValue {
+    if input.len() <= CHUNK_SIZE {
+        return hash_node_maybe_keyed(input, Finalization::NotRoot, key);
+    }
+    let split = left_len(input.len() as u64) as usize;
+    let (left, right) = input.split_at(split);
+    #[cfg(feature = "rayon")]
+    let (left_cv, right_cv) = rayon::join(
+        || hash_subtree_maybe_keyed(left, key),
+        || hash_subtree_maybe_keyed(right, key),
+    );
+    #[cfg(not(feature = "rayon"))]
+    let (left_cv, right_cv) = (
+        hash_subtree_maybe_keyed(left, key),
+        hash_subtree_maybe_keyed(right, key),
+    );
+    merge_maybe_keyed(&left_cv, &right_cv, key)
+}
+
+/// Combine two child chaining values into their parent's chaining value. The split between any
+/// subtree's left and right children always follows `left_len`'s rule (the left child is the
+/// largest power-of-two number of chunks strictly less than the total), so a coordinator that
+/// respects that same split when dividing work among workers reconstructs bit-for-bit the same
+/// root as `hash::hash`.
+pub fn merge(left_cv: &ChainingValue, right_cv: &ChainingValue) -> ChainingValue {
+    merge_maybe_keyed(left_cv, right_cv, None)
+}
+
+fn merge_maybe_keyed(left_cv: &ChainingValue, right_cv: &ChainingValue, key: Option<&Key>) -> ChainingValue {
+    let mut parent = [0; PARENT_SIZE];
+    parent[..HASH_SIZE].copy_from_slice(left_cv);
+    parent[HASH_SIZE..].copy_from_slice(right_cv);
+    hash_node_maybe_keyed(&parent, Finalization::NotRoot, key)
+}
+
+/// Combine the two children of the *root* parent node into the final `Hash` that
+/// `hash::hash(whole_input)` would have produced. Unlike `merge`, this hashes the 64-byte parent
+/// node itself under `Finalization::Root(total_len)` rather than re-hashing an already-hashed,
+/// 32-byte chaining value; a root is a property of *which bytes get hashed*, not something you can
+/// fold on top of a finished chaining value after the fact. This is the last step a coordinator
+/// runs, after folding every worker's shard together with `merge` down to the two children of the
+/// root.
+///
+/// If the whole input is a single chunk (no parent node at all), there's nothing to merge: hash
+/// that chunk directly with `hash_node(chunk, Finalization::Root(total_len))` instead of calling
+/// `hash_subtree`/`merge_root` at all.
+pub fn merge_root(left_cv: &ChainingValue, right_cv: &ChainingValue, total_len: u64) -> Hash {
+    merge_root_maybe_keyed(left_cv, right_cv, total_len, None)
+}
+
+fn merge_root_maybe_keyed(
+    left_cv: &ChainingValue,
+    right_cv: &ChainingValue,
+    total_len: u64,
+    key: Option<&Key>,
+) -> Hash {
+    let mut parent = [0; PARENT_SIZE];
+    parent[..HASH_SIZE].copy_from_slice(left_cv);
+    parent[HASH_SIZE..].copy_from_slice(right_cv);
+    hash_node_maybe_keyed(&parent, Finalization::Root(total_len), key)
+}
+
+/// An incremental, streaming counterpart to `hash`/`hash_keyed`: bytes can arrive across any
+/// number of `write_all` calls, and the total length never needs to be known up front. This keeps
+/// a stack of already-finalized, `NotRoot` subtree chaining values -- one per complete run of
+/// chunks whose size is a power of two, mirroring the same left/right split `left_len` uses -- plus
+/// the one chunk that's still being filled. `finish` folds that stack down to a single root hash,
+/// finalizing the final chunk (or, if the input never grew past one chunk, the whole input) only
+/// at that point, once it's clear no more bytes are coming.
+pub struct Writer {
+    key: Option<Key>,
+    stack: ArrayVec<[(ChainingValue, u64); MAX_DEPTH]>,
+    buf: [u8; CHUNK_SIZE],
+    buf_len: usize,
+    bytes_before_buf: u64,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::new_maybe_keyed(None)
+    }
+
+    /// Like `new`, but every node is keyed with `key`, producing the same root `hash_keyed` would.
+    pub fn new_keyed(key: &Key) -> Self {
+        Self::new_maybe_keyed(Some(*key))
+    }
+
+    fn new_maybe_keyed(key: Option<Key>) -> Self {
+        Self {
+            key,
+            stack: ArrayVec::new(),
+            buf: [0; CHUNK_SIZE],
+            buf_len: 0,
+            bytes_before_buf: 0,
+        }
+    }
+
+    fn total_len(&self) -> u64 {
+        self.bytes_before_buf + self.buf_len as u64
+    }
+
+    pub fn write_all(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            if self.buf_len == CHUNK_SIZE {
+                self.finalize_buf_chunk();
+            }
+            let take = cmp::min(input.len(), CHUNK_SIZE - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&input[..take]);
+            self.buf_len += take;
+            input = &input[take..];
+        }
+    }
+
+    // Only called once we know a later chunk follows, so this chunk can never be the root.
+    fn finalize_buf_chunk(&mut self) {
+        let cv = hash_node_maybe_keyed(&self.buf[..self.buf_len], Finalization::NotRoot, self.key.as_ref());
+        self.push_subtree(cv, 1);
+        self.bytes_before_buf += self.buf_len as u64;
+        self.buf_len = 0;
+    }
+
+    // Carry-propagate the new subtree into the stack, merging any run of equal-sized subtrees at
+    // the top, the same way `left_len` splits a subtree in two equal-or-larger-on-the-left halves.
+    fn push_subtree(&mut self, mut cv: ChainingValue, mut chunks: u64) {
+        while let Some(&(_, top_chunks)) = self.stack.last() {
+            if top_chunks != chunks {
+                break;
+            }
+            let (left_cv, _) = self.stack.pop().unwrap();
+            cv = merge_maybe_keyed(&left_cv, &cv, self.key.as_ref());
+            chunks *= 2;
+        }
+        self.stack.push((cv, chunks));
+    }
+
+    pub fn finish(mut self) -> Hash {
+        let total_len = self.total_len();
+        if self.stack.is_empty() {
+            // The whole input fit in a single chunk (including the empty input), so it's its own
+            // root; there's no parent node to fold `merge_root` over.
+            return hash_node_maybe_keyed(&self.buf[..self.buf_len], Finalization::Root(total_len), self.key.as_ref());
+        }
+        let mut acc = hash_node_maybe_keyed(&self.buf[..self.buf_len], Finalization::NotRoot, self.key.as_ref());
+        loop {
+            let (left_cv, _) = self.stack.pop().expect("stack can't run out before the root");
+            if self.stack.is_empty() {
+                return merge_root_maybe_keyed(&left_cv, &acc, total_len, self.key.as_ref());
+            }
+            acc = merge_maybe_keyed(&left_cv, &acc, self.key.as_ref());
+        }
+    }
+}
+
+/// Like `Writer`, but `finish` hashes the buffered input with `hash_subtree`'s split/merge
+/// recursion instead of folding an incremental stack, so that it can farm the two halves of every
+/// split out to `rayon::join` (when the `rayon` feature is on). Buffering the whole input costs
+/// more memory than `Writer`'s bounded stack, but for large inputs on a multi-core machine the
+/// parallel hashing more than pays for it.
+#[cfg(feature = "std")]
+pub struct ParallelWriter {
+    key: Option<Key>,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl ParallelWriter {
+    pub fn new() -> Self {
+        Self::new_maybe_keyed(None)
+    }
+
+    pub fn new_keyed(key: &Key) -> Self {
+        Self::new_maybe_keyed(Some(*key))
+    }
+
+    fn new_maybe_keyed(key: Option<Key>) -> Self {
+        Self { key, buf: Vec::new() }
+    }
+
+    pub fn write_all(&mut self, input: &[u8]) {
+        self.buf.extend_from_slice(input);
+    }
+
+    pub fn finish(self) -> Hash {
+        let total_len = self.buf.len() as u64;
+        if self.buf.len() <= CHUNK_SIZE {
+            return hash_node_maybe_keyed(&self.buf, Finalization::Root(total_len), self.key.as_ref());
+        }
+        let split = left_len(total_len) as usize;
+        let (left_input, right_input) = self.buf.split_at(split);
+        let key = self.key.as_ref();
+        #[cfg(feature = "rayon")]
+        let (left_cv, right_cv) = rayon::join(
+            || hash_subtree_maybe_keyed(left_input, key),
+            || hash_subtree_maybe_keyed(right_input, key),
+        );
+        #[cfg(not(feature = "rayon"))]
+        let (left_cv, right_cv) = (
+            hash_subtree_maybe_keyed(left_input, key),
+            hash_subtree_maybe_keyed(right_input, key),
+        );
+        merge_root_maybe_keyed(&left_cv, &right_cv, total_len, key)
+    }
+}
+
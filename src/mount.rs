@@ -0,0 +1,237 @@
+//! A read-only FUSE filesystem that exposes a single bao-encoded file as plain content, verifying
+//! every byte against the root hash as it's served. This is the library half of `bao mount`; see
+//! bao_bin for the CLI wrapper.
+//!
+//! Like the chunked-backup FUSE layer in proxmox-backup, each kernel `read` is translated into a
+//! `Seek` followed by a bounded `Read` against a `decode::Reader`, so the kernel never sees a byte
+//! that hasn't been hash-verified on the fly. `decode::State` is `Clone`, so rather than share one
+//! `Reader` (and serialize every read behind a lock), we hand out a fresh `Reader` per request,
+//! each seeking independently on its own clone of the underlying file handle -- but all of them
+//! resume from a `State` that's already past the header, cloned off `BaoFilesystem::root_state`,
+//! instead of re-parsing the header and re-deriving the content length on every single read.
+
+extern crate fuse;
+extern crate libc;
+extern crate time;
+
+use decode;
+use hash::Hash;
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use self::fuse::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyEntry, Request};
+use self::time::Timespec;
+
+const TTL: Timespec = Timespec { sec: 60, nsec: 0 };
+const ROOT_INODE: u64 = 1;
+const FILE_INODE: u64 = 2;
+
+/// A single-file, read-only FUSE filesystem backed by a bao encoding.
+pub struct BaoFilesystem {
+    file: File,
+    file_name: String,
+    content_length: u64,
+    /// A `decode::State` that has already read the header and knows `content_length`, cached so
+    /// each FUSE read can `.clone()` it instead of re-parsing the 8-byte header and re-verifying
+    /// the content length. `State` is `Clone` precisely to make this kind of warm start cheap.
+    root_state: decode::State,
+}
+
+impl BaoFilesystem {
+    /// Open `encoded_path` and verify its header against `hash`, so that the reported file size
+    /// (and the mount itself) fail fast if the root doesn't match.
+    pub fn new(hash: Hash, encoded_path: &Path) -> io::Result<Self> {
+        let file = File::open(encoded_path)?;
+        let mut header_reader = decode::Reader::new(file.try_clone()?, hash);
+        // Seeking to the end forces the header to be read and the root node verified, without
+        // pulling in any content, and hands back the verified content length as a side effect.
+        let content_length = header_reader.seek(SeekFrom::End(0))?;
+        // Then seek back to the start, so the cached state sits at the root of the tree rather
+        // than at EOF, and every later seek can descend from there to its own target.
+        header_reader.seek(SeekFrom::Start(0))?;
+        let root_state = header_reader.state().clone();
+        let file_name = encoded_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "content".to_string());
+        Ok(Self {
+            file,
+            file_name,
+            content_length,
+            root_state,
+        })
+    }
+
+    fn file_attr(&self) -> FileAttr {
+        let now = UNIX_EPOCH.elapsed().unwrap_or(Duration::from_secs(0));
+        let epoch = Timespec::new(now.as_secs() as i64, now.subsec_nanos() as i32);
+        FileAttr {
+            ino: FILE_INODE,
+            size: self.content_length,
+            blocks: (self.content_length + 511) / 512,
+            atime: epoch,
+            mtime: epoch,
+            ctime: epoch,
+            crtime: epoch,
+            kind: FileType::RegularFile,
+            perm: 0o444,
+            nlink: 1,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    fn root_attr(&self) -> FileAttr {
+        let mut attr = self.file_attr();
+        attr.ino = ROOT_INODE;
+        attr.kind = FileType::Directory;
+        attr.perm = 0o555;
+        attr.nlink = 2;
+        attr
+    }
+
+    /// Read `size` verified bytes starting at content offset `offset`, by seeking a `decode::Reader`
+    /// over our own clone of the file handle and reading up to `size` bytes. The reader resumes
+    /// from `root_state` (already past the header, with the content length known) rather than
+    /// starting over at byte zero, so each read only has to walk the tree nodes its own offset
+    /// actually needs.
+    fn verified_read(&self, offset: u64, size: u32) -> io::Result<Vec<u8>> {
+        let inner = self.file.try_clone()?;
+        let mut reader = decode::Reader::with_state(inner, self.root_state.clone());
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0; size as usize];
+        let mut total = 0;
+        while total < buf.len() {
+            let n = reader.read(&mut buf[total..])?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        buf.truncate(total);
+        Ok(buf)
+    }
+}
+
+impl Filesystem for BaoFilesystem {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if parent == ROOT_INODE && name == OsStr::new(&self.file_name) {
+            reply.entry(&TTL, &self.file_attr(), 0);
+        } else {
+            reply.error(libc::ENOENT);
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match ino {
+            ROOT_INODE => reply.attr(&TTL, &self.root_attr()),
+            FILE_INODE => reply.attr(&TTL, &self.file_attr()),
+            _ => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        reply: ReplyData,
+    ) {
+        if ino != FILE_INODE {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        match self.verified_read(offset as u64, size) {
+            Ok(data) => reply.data(&data),
+            // A hash mismatch (or any other verification failure) surfaces to the kernel as EIO,
+            // the standard signal for "this data is corrupt, don't retry blindly".
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+}
+
+/// Mount `encoded_path`, which must decode under `hash`, as a single read-only file at
+/// `mountpoint`. This call blocks until the filesystem is unmounted.
+pub fn mount(hash: Hash, encoded_path: &Path, mountpoint: &Path) -> io::Result<()> {
+    let fs = BaoFilesystem::new(hash, encoded_path)?;
+    fuse::mount(fs, &mountpoint, &[]).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+// The `fuse` crate's `Request`/`ReplyData` types can't be constructed outside of a live kernel
+// mount, so `Filesystem::read` itself isn't reachable from a test. `verified_read` carries all of
+// the actual verification logic `read` delegates to, though, so it's tested directly here instead.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encode;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static TEST_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    /// A path under the system temp dir that's unique to this process and this call, so
+    /// concurrent test runs don't collide.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let n = TEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("bao-mount-test-{}-{}-{}", std::process::id(), n, name))
+    }
+
+    fn make_input(len: usize) -> Vec<u8> {
+        let mut counter: u32 = 1;
+        let mut output = Vec::with_capacity(len);
+        while output.len() < len {
+            let bytes = counter.to_le_bytes();
+            let take = std::cmp::min(4, len - output.len());
+            output.extend_from_slice(&bytes[..take]);
+            counter += 1;
+        }
+        output
+    }
+
+    fn write_encoded(input: &[u8], path: &Path) -> Hash {
+        let file = File::create(path).unwrap();
+        let mut writer = encode::Writer::new(file);
+        writer.write_all(input).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_verified_read_returns_requested_range() {
+        let input = make_input(2 * ::hash::CHUNK_SIZE + 500);
+        let path = temp_path("roundtrip.bao");
+        let hash = write_encoded(&input, &path);
+
+        let fs = BaoFilesystem::new(hash, &path).unwrap();
+        let bytes = fs.verified_read(100, 300).unwrap();
+        assert_eq!(&input[100..400], &bytes[..]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verified_read_rejects_corrupted_content() {
+        let input = make_input(2 * ::hash::CHUNK_SIZE + 500);
+        let path = temp_path("corrupt.bao");
+        let hash = write_encoded(&input, &path);
+
+        // Flip the last content byte, so the file still opens and its length still checks out,
+        // but the chunk covering this read no longer matches the root hash.
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let fs = BaoFilesystem::new(hash, &path).unwrap();
+        assert!(fs.verified_read(input.len() as u64 - 50, 50).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
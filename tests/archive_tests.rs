@@ -0,0 +1,43 @@
+//! Behavioral tests for the `archive` module: a tampered catalog fails verification before any
+//! member content is trusted, and `safe_member_path` rejects entry paths that would escape the
+//! extraction directory.
+
+extern crate bao;
+
+use std::io::Cursor;
+
+#[test]
+fn test_read_catalog_rejects_tampered_archive() {
+    let members = vec![
+        ("a.txt".to_string(), b"hello".to_vec()),
+        ("b.txt".to_string(), b"world, this is the second member".to_vec()),
+    ];
+    let mut archive_bytes = Vec::new();
+    let archive_hash = bao::archive::archive(&members, &mut archive_bytes).unwrap();
+
+    // A clean archive's catalog reads back exactly as written.
+    let catalog = bao::archive::read_catalog(Cursor::new(&archive_bytes), &archive_hash).unwrap();
+    assert_eq!(2, catalog.entries.len());
+    assert_eq!("a.txt", catalog.entries[0].path);
+
+    // Flipping a byte anywhere in the catalog's encoding must be caught before any entry is
+    // trusted, rather than silently handing back a forged path or length.
+    let mut tampered = archive_bytes.clone();
+    tampered[bao::hash::HEADER_SIZE] ^= 1;
+    assert!(bao::archive::read_catalog(Cursor::new(&tampered), &archive_hash).is_err());
+}
+
+#[test]
+fn test_safe_member_path_accepts_normal_names() {
+    let dest = std::path::Path::new("/tmp/bao-unarchive-dest");
+    let resolved = bao::archive::safe_member_path(dest, "subdir/file.txt").unwrap();
+    assert_eq!(dest.join("subdir/file.txt"), resolved);
+}
+
+#[test]
+fn test_safe_member_path_rejects_traversal_and_absolute_paths() {
+    let dest = std::path::Path::new("/tmp/bao-unarchive-dest");
+    assert!(bao::archive::safe_member_path(dest, "../../etc/passwd").is_err());
+    assert!(bao::archive::safe_member_path(dest, "/etc/passwd").is_err());
+    assert!(bao::archive::safe_member_path(dest, "subdir/../../escape").is_err());
+}
@@ -0,0 +1,132 @@
+//! Behavioral test for `async_io::AsyncReader::poll_seek`: suspending on `Poll::Pending` from the
+//! underlying transport and resuming cleanly on a later poll, rather than restarting the seek (and
+//! double-verifying a node) from scratch.
+
+#![cfg(feature = "tokio")]
+
+extern crate bao;
+extern crate tokio;
+
+use bao::async_io::AsyncReader;
+use std::cell::Cell;
+use std::io;
+use std::io::prelude::*;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use tokio::io::{AsyncRead, AsyncSeek};
+
+/// A transport wrapping an in-memory buffer that returns `Poll::Pending` for its first
+/// `pending_budget` polls (of either `poll_read` or `poll_seek`) before actually servicing them,
+/// simulating a slow network peer.
+struct FlakyTransport {
+    inner: io::Cursor<Vec<u8>>,
+    pending_budget: Cell<u32>,
+}
+
+impl FlakyTransport {
+    fn new(data: Vec<u8>, pending_budget: u32) -> Self {
+        Self {
+            inner: io::Cursor::new(data),
+            pending_budget: Cell::new(pending_budget),
+        }
+    }
+
+    fn take_pending(&self, cx: &Context) -> bool {
+        let budget = self.pending_budget.get();
+        if budget > 0 {
+            self.pending_budget.set(budget - 1);
+            cx.waker().wake_by_ref();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl AsyncRead for FlakyTransport {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.take_pending(cx) {
+            return Poll::Pending;
+        }
+        Poll::Ready(this.inner.read(buf))
+    }
+}
+
+impl AsyncSeek for FlakyTransport {
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context, pos: io::SeekFrom) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        if this.take_pending(cx) {
+            return Poll::Pending;
+        }
+        Poll::Ready(this.inner.seek(pos))
+    }
+}
+
+fn noop_waker() -> Waker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
+fn make_input(len: usize) -> Vec<u8> {
+    let mut counter: u32 = 1;
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let bytes = counter.to_le_bytes();
+        let take = std::cmp::min(4, len - output.len());
+        output.extend_from_slice(&bytes[..take]);
+        counter += 1;
+    }
+    output
+}
+
+#[test]
+fn test_poll_seek_suspends_on_pending_and_resumes() {
+    let input = make_input(2 * bao::hash::CHUNK_SIZE + 500);
+    let mut encoded = Vec::new();
+    let root_hash = {
+        let mut w = bao::encode::Writer::new(&mut encoded);
+        w.write_all(&input).unwrap();
+        w.finish().unwrap()
+    };
+
+    // Stall the first several polls, so `poll_seek` is guaranteed to see at least one `Pending`
+    // partway through the header/root-parent walk it has to do before it can even compute the
+    // target offset.
+    let transport = FlakyTransport::new(encoded, 5);
+    let mut reader = AsyncReader::new(transport, root_hash);
+
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let target = bao::hash::CHUNK_SIZE as u64 + 10;
+    let mut pending_count = 0;
+    let position = loop {
+        match Pin::new(&mut reader).poll_seek(&mut cx, io::SeekFrom::Start(target)) {
+            Poll::Pending => {
+                pending_count += 1;
+                assert!(pending_count < 10_000, "poll_seek never resolved");
+            }
+            Poll::Ready(result) => break result.unwrap(),
+        }
+    };
+    assert!(pending_count > 0, "test transport never actually returned Pending");
+    assert_eq!(target, position);
+
+    // Reading from here on should pick up exactly where the seek landed.
+    let mut got = vec![0u8; 50];
+    let mut filled = 0;
+    while filled < got.len() {
+        match Pin::new(&mut reader).poll_read(&mut cx, &mut got[filled..]) {
+            Poll::Pending => continue,
+            Poll::Ready(Ok(0)) => panic!("unexpected EOF"),
+            Poll::Ready(Ok(n)) => filled += n,
+            Poll::Ready(Err(e)) => panic!("read failed: {}", e),
+        }
+    }
+    assert_eq!(&input[target as usize..target as usize + 50], &got[..]);
+}
@@ -0,0 +1,139 @@
+//! Behavioral tests for the feature work added on top of `vector_tests.rs` (parallel encoding,
+//! the keyed MAC mode, the streaming `encode::Writer`, and multi-range slicing). `vector_tests.rs`
+//! already covers the original serial encode/decode path against the upstream test vectors; these
+//! exercise the newer pieces the same way, by cross-checking independent implementations of the
+//! same hash against each other rather than against a fixture file.
+
+extern crate bao;
+
+use std::io::prelude::*;
+use std::io::Cursor;
+
+/// A deterministic, non-repeating input, long enough to span several chunks and a few levels of
+/// the tree. Mirrors `vector_tests.rs::make_input`, just inline since this file doesn't share that
+/// module.
+fn make_input(len: usize) -> Vec<u8> {
+    let mut counter: u32 = 1;
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let bytes = counter.to_le_bytes();
+        let take = std::cmp::min(4, len - output.len());
+        output.extend_from_slice(&bytes[..take]);
+        counter += 1;
+    }
+    output
+}
+
+const MULTI_CHUNK_LEN: usize = 2 * bao::hash::CHUNK_SIZE + 500;
+
+#[test]
+#[cfg(feature = "rayon")]
+fn test_encode_parallel_matches_streaming_hash() {
+    let input = make_input(MULTI_CHUNK_LEN);
+
+    let mut writer = bao::hash::Writer::new();
+    writer.write_all(&input);
+    let expected_hash = writer.finish();
+
+    let encoded_size = bao::hash::HEADER_SIZE as u128 + bao::encode::encoded_subtree_size(input.len() as u64);
+    let mut encoded = vec![0; encoded_size as usize];
+    let hash = bao::encode::encode_parallel(&input, &mut encoded);
+    assert_eq!(expected_hash, hash);
+
+    let mut header = [0u8; bao::hash::HEADER_SIZE];
+    header.copy_from_slice(&encoded[..bao::hash::HEADER_SIZE]);
+    assert_eq!(input.len() as u64, bao::hash::decode_len(header));
+}
+
+#[test]
+fn test_encode_keyed_matches_hash_keyed() {
+    let input = make_input(MULTI_CHUNK_LEN);
+    let key = [42; bao::hash::HASH_SIZE];
+
+    let expected_hash = bao::hash::hash_keyed(&key, &input);
+
+    let mut writer = bao::hash::Writer::new_keyed(&key);
+    writer.write_all(&input);
+    assert_eq!(expected_hash, writer.finish());
+
+    let encoded_size = bao::hash::HEADER_SIZE as u128 + bao::encode::encoded_subtree_size(input.len() as u64);
+    let mut encoded = vec![0; encoded_size as usize];
+    let hash = bao::encode::encode_keyed(&key, &input, &mut encoded);
+    assert_eq!(expected_hash, hash);
+}
+
+#[test]
+fn test_encode_writer_matches_keyed_recursion() {
+    let input = make_input(MULTI_CHUNK_LEN);
+    let key = [7; bao::hash::HASH_SIZE];
+
+    let mut recursive_encoded =
+        vec![0; bao::hash::HEADER_SIZE + bao::encode::encoded_subtree_size(input.len() as u64) as usize];
+    let expected_hash = bao::encode::encode_keyed(&key, &input, &mut recursive_encoded);
+
+    let mut output = Vec::new();
+    let mut writer = bao::encode::Writer::new_keyed(Cursor::new(&mut output), &key);
+    // Drive `write_vectored` as well as plain `write`, split across a couple of calls, to exercise
+    // both entry points into the same accumulation buffer.
+    let split = input.len() / 3;
+    writer
+        .write_vectored(&[std::io::IoSlice::new(&input[..split]), std::io::IoSlice::new(&input[split..])])
+        .unwrap();
+    let hash = writer.finish().unwrap();
+
+    assert_eq!(expected_hash, hash);
+    assert_eq!(recursive_encoded, output);
+}
+
+#[test]
+fn test_decode_reader_roundtrip_and_rejects_corruption() {
+    let input = make_input(MULTI_CHUNK_LEN);
+    let key = [99; bao::hash::HASH_SIZE];
+
+    let mut encoded = vec![0; bao::hash::HEADER_SIZE + bao::encode::encoded_subtree_size(input.len() as u64) as usize];
+    let hash = bao::encode::encode_keyed(&key, &input, &mut encoded);
+
+    let mut reader = bao::decode::Reader::new_keyed(&*encoded, hash, key);
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).unwrap();
+    assert_eq!(input, output);
+
+    // Flipping a content byte must fail the read rather than silently returning bad bytes.
+    let mut corrupt = encoded.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    let mut reader = bao::decode::Reader::new_keyed(&*corrupt, hash, key);
+    let mut output = Vec::new();
+    assert!(reader.read_to_end(&mut output).is_err());
+}
+
+#[test]
+fn test_multi_range_slice_roundtrip() {
+    let input = make_input(MULTI_CHUNK_LEN);
+    let key = [3; bao::hash::HASH_SIZE];
+
+    let mut encoded = vec![0; bao::hash::HEADER_SIZE + bao::encode::encoded_subtree_size(input.len() as u64) as usize];
+    let hash = bao::encode::encode_keyed(&key, &input, &mut encoded);
+
+    // Two disjoint ranges: the first sits entirely inside the first chunk, and the second straddles
+    // the boundary into the second chunk, exercising both the buffered-chunk-reuse path and a range
+    // that needs more than one chunk's worth of `Subtree` nodes.
+    let ranges = vec![(0u64, 100u64), (bao::hash::CHUNK_SIZE as u64 - 50, 200u64)];
+
+    let mut slice_bytes = Vec::new();
+    {
+        let mut extractor = bao::encode::SliceExtractor::new(Cursor::new(&encoded), input.len() as u64, &ranges);
+        extractor.read_to_end(&mut slice_bytes).unwrap();
+    }
+    // The slice should be substantially smaller than the full encoding.
+    assert!(slice_bytes.len() < encoded.len());
+
+    let mut slice_reader = bao::decode::SliceReader::new_keyed(Cursor::new(&slice_bytes), hash, key, ranges.clone());
+    let mut sliced_output = Vec::new();
+    slice_reader.read_to_end(&mut sliced_output).unwrap();
+
+    let mut expected = Vec::new();
+    for &(start, len) in &ranges {
+        expected.extend_from_slice(&input[start as usize..(start + len) as usize]);
+    }
+    assert_eq!(expected, sliced_output);
+}
@@ -0,0 +1,90 @@
+//! Behavioral tests for the low-level distributed-hashing API (`hash::hash_subtree`/`hash::merge`/
+//! `hash::merge_root`): hashing chunk-aligned shards independently and folding them back together
+//! must reproduce exactly what the streaming `hash::Writer` computes over the same bytes.
+
+extern crate bao;
+
+use bao::hash;
+
+fn make_input(len: usize) -> Vec<u8> {
+    let mut counter: u32 = 1;
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let bytes = counter.to_le_bytes();
+        let take = std::cmp::min(4, len - output.len());
+        output.extend_from_slice(&bytes[..take]);
+        counter += 1;
+    }
+    output
+}
+
+fn streaming_hash(input: &[u8]) -> bao::hash::Hash {
+    let mut writer = hash::Writer::new();
+    writer.write_all(input);
+    writer.finish()
+}
+
+#[test]
+fn test_two_shard_merge_root_matches_streaming_hash() {
+    let input = make_input(2 * hash::CHUNK_SIZE + 500);
+    let expected = streaming_hash(&input);
+
+    // A coordinator splitting work between exactly two workers: each worker hashes its own
+    // chunk-aligned shard with `hash_subtree`, and the coordinator folds the two chaining values
+    // together with `merge_root` using the split that `left_len` dictates.
+    let split = hash::left_len(input.len() as u64) as usize;
+    let (left, right) = input.split_at(split);
+    let left_cv = hash::hash_subtree(left, 0);
+    let right_cv = hash::hash_subtree(right, (split / hash::CHUNK_SIZE) as u64);
+    let root = hash::merge_root(&left_cv, &right_cv, input.len() as u64);
+
+    assert_eq!(expected, root);
+}
+
+#[test]
+fn test_four_shard_merge_then_merge_root_matches_streaming_hash() {
+    let input = make_input(6 * hash::CHUNK_SIZE + 500);
+    let expected = streaming_hash(&input);
+
+    // Split into the same two top-level shards `left_len` would produce, then split each of
+    // those again at its own `left_len`, giving four independently-hashable shards that recombine through two
+    // levels of `merge` before the final `merge_root`.
+    let top_split = hash::left_len(input.len() as u64) as usize;
+    let (top_left, top_right) = input.split_at(top_split);
+
+    let left_split = hash::left_len(top_left.len() as u64) as usize;
+    let (ll, lr) = top_left.split_at(left_split);
+    let right_split = hash::left_len(top_right.len() as u64) as usize;
+    let (rl, rr) = top_right.split_at(right_split);
+
+    let ll_cv = hash::hash_subtree(ll, 0);
+    let lr_cv = hash::hash_subtree(lr, 0);
+    let rl_cv = hash::hash_subtree(rl, 0);
+    let rr_cv = hash::hash_subtree(rr, 0);
+
+    let left_cv = hash::merge(&ll_cv, &lr_cv);
+    let right_cv = hash::merge(&rl_cv, &rr_cv);
+    let root = hash::merge_root(&left_cv, &right_cv, input.len() as u64);
+
+    assert_eq!(expected, root);
+}
+
+#[test]
+fn test_merge_root_diverges_from_hashing_the_chaining_value_again() {
+    // Guards against the historical bug this API replaced: root-finalizing an already-reduced
+    // chaining value (re-hashing 32 bytes under `Finalization::Root`) is a different computation
+    // from `merge_root`'s real parent-node hash, and must not coincidentally produce the same
+    // root.
+    let input = make_input(2 * hash::CHUNK_SIZE + 500);
+    let expected = streaming_hash(&input);
+
+    let split = hash::left_len(input.len() as u64) as usize;
+    let (left, right) = input.split_at(split);
+    let left_cv = hash::hash_subtree(left, 0);
+    let right_cv = hash::hash_subtree(right, 0);
+    let combined_cv = hash::merge(&left_cv, &right_cv);
+    let bogus_root = hash::hash_node(&combined_cv, hash::Finalization::Root(input.len() as u64));
+
+    assert_ne!(expected, bogus_root);
+    assert_eq!(expected, hash::merge_root(&left_cv, &right_cv, input.len() as u64));
+}
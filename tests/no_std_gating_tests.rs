@@ -0,0 +1,109 @@
+//! A smoke test for the `no_std`/`core_io` gating added to `decode`: `decode::State`'s
+//! `read_next`/`feed_*` state machine is the part meant to keep working without `std` (only
+//! `decode::SliceReader` and `decode::store` are `std`-only, since they need an allocator),
+//! so this drives `State` directly against a plain `&[u8]`, tracking its own read cursor exactly
+//! the way `decode::Reader` tracks its position in an underlying stream -- instead of going
+//! through `Reader` itself.
+//!
+//! This sandbox has no `no_std` target or `Cargo.toml` to actually compile the `core_io` path
+//! against, so it can't prove the crate builds with `--no-default-features`; what it can prove is
+//! that the std-agnostic half of the machine, `State`, verifies correctly on its own.
+
+extern crate bao;
+
+use bao::decode::{State, StateNext};
+use std::io::Write;
+
+fn make_input(len: usize) -> Vec<u8> {
+    let mut counter: u32 = 1;
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let bytes = counter.to_le_bytes();
+        let take = std::cmp::min(4, len - output.len());
+        output.extend_from_slice(&bytes[..take]);
+        counter += 1;
+    }
+    output
+}
+
+fn encode(input: &[u8]) -> (bao::hash::Hash, Vec<u8>) {
+    let mut encoded = Vec::new();
+    let mut w = bao::encode::Writer::new(&mut encoded);
+    w.write_all(input).unwrap();
+    let hash = w.finish().unwrap();
+    (hash, encoded)
+}
+
+#[test]
+fn test_state_machine_verifies_without_going_through_reader() {
+    let input = make_input(2 * bao::hash::CHUNK_SIZE + 500);
+    let (root_hash, encoded) = encode(&input);
+
+    let mut state = State::new(root_hash);
+    let mut pos = 0usize;
+    let mut output = Vec::new();
+    loop {
+        match state.read_next() {
+            StateNext::Header => {
+                let mut header = [0u8; bao::hash::HEADER_SIZE];
+                header.copy_from_slice(&encoded[pos..pos + bao::hash::HEADER_SIZE]);
+                pos += bao::hash::HEADER_SIZE;
+                state.feed_header(header);
+            }
+            StateNext::Subtree { .. } => {
+                let mut parent = [0u8; bao::hash::PARENT_SIZE];
+                parent.copy_from_slice(&encoded[pos..pos + bao::hash::PARENT_SIZE]);
+                pos += bao::hash::PARENT_SIZE;
+                state.feed_parent(parent).expect("parent hash mismatch");
+            }
+            StateNext::Chunk { size, skip, finalization } => {
+                let chunk = &encoded[pos..pos + size];
+                pos += size;
+                let hash = bao::hash::hash_node(chunk, finalization);
+                state.feed_subtree(hash).expect("chunk hash mismatch");
+                output.extend_from_slice(&chunk[skip..]);
+            }
+            StateNext::Done => break,
+        }
+    }
+    assert_eq!(input, output);
+}
+
+#[test]
+fn test_state_machine_rejects_corrupted_chunk() {
+    let input = make_input(2 * bao::hash::CHUNK_SIZE + 500);
+    let (root_hash, mut encoded) = encode(&input);
+    *encoded.last_mut().unwrap() ^= 1;
+
+    let mut state = State::new(root_hash);
+    let mut pos = 0usize;
+    loop {
+        match state.read_next() {
+            StateNext::Header => {
+                let mut header = [0u8; bao::hash::HEADER_SIZE];
+                header.copy_from_slice(&encoded[pos..pos + bao::hash::HEADER_SIZE]);
+                pos += bao::hash::HEADER_SIZE;
+                state.feed_header(header);
+            }
+            StateNext::Subtree { .. } => {
+                let mut parent = [0u8; bao::hash::PARENT_SIZE];
+                parent.copy_from_slice(&encoded[pos..pos + bao::hash::PARENT_SIZE]);
+                pos += bao::hash::PARENT_SIZE;
+                state.feed_parent(parent).expect("parent hash mismatch");
+            }
+            StateNext::Chunk { size, skip: _, finalization } => {
+                let chunk = &encoded[pos..pos + size];
+                pos += size;
+                let hash = bao::hash::hash_node(chunk, finalization);
+                let result = state.feed_subtree(hash);
+                if pos == encoded.len() {
+                    // Only the final chunk holds the flipped byte.
+                    assert!(result.is_err());
+                    return;
+                }
+                result.expect("chunk hash mismatch");
+            }
+            StateNext::Done => panic!("corrupted chunk should have been rejected before EOF"),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Behavioral tests for `decode::Reader::read_to_at`/`write_from_at`, the zero-copy positioned
+//! I/O pair modeled on crosvm's `ZeroCopyReader`/`ZeroCopyWriter`. These need real `std::fs::File`
+//! handles (both methods are specialized to that type), so unlike `backlog_tests.rs` they write
+//! through a temp file on disk instead of an in-memory `Cursor`.
+
+extern crate bao;
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static TEST_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    let n = TEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("bao-positioned-io-test-{}-{}-{}", std::process::id(), n, name))
+}
+
+fn make_input(len: usize) -> Vec<u8> {
+    let mut counter: u32 = 1;
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let bytes = counter.to_le_bytes();
+        let take = std::cmp::min(4, len - output.len());
+        output.extend_from_slice(&bytes[..take]);
+        counter += 1;
+    }
+    output
+}
+
+const MULTI_CHUNK_LEN: usize = 2 * bao::hash::CHUNK_SIZE + 500;
+
+#[test]
+fn test_read_to_at_splices_verified_bytes_into_dest() {
+    let input = make_input(MULTI_CHUNK_LEN);
+
+    let encoded_path = temp_path("encoded.bao");
+    let hash = {
+        let file = File::create(&encoded_path).unwrap();
+        let mut writer = bao::encode::Writer::new(file);
+        writer.write_all(&input).unwrap();
+        writer.finish().unwrap()
+    };
+
+    let encoded_file = File::open(&encoded_path).unwrap();
+    let mut reader = bao::decode::Reader::new(encoded_file, hash);
+
+    let dest_path = temp_path("dest.bin");
+    let mut dest = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&dest_path)
+        .unwrap();
+
+    let offset = bao::hash::CHUNK_SIZE as u64 - 50;
+    let written = reader.read_to_at(&mut dest, 300, offset).unwrap();
+    assert_eq!(300, written);
+
+    let mut got = Vec::new();
+    dest.seek(std::io::SeekFrom::Start(0)).unwrap();
+    dest.read_to_end(&mut got).unwrap();
+    assert_eq!(&input[offset as usize..offset as usize + 300], &got[..]);
+
+    fs::remove_file(&encoded_path).unwrap();
+    fs::remove_file(&dest_path).unwrap();
+}
+
+#[test]
+fn test_write_from_at_then_read_back_verifies() {
+    let input = make_input(MULTI_CHUNK_LEN);
+
+    let mut raw_encoded = Vec::new();
+    let hash = {
+        let mut writer = bao::encode::Writer::new(&mut raw_encoded);
+        writer.write_all(&input).unwrap();
+        writer.finish().unwrap()
+    };
+
+    let src_path = temp_path("src.bin");
+    fs::write(&src_path, &raw_encoded).unwrap();
+    let mut src = File::open(&src_path).unwrap();
+
+    // Start from an empty encoded file and splice the whole thing in through write_from_at,
+    // exactly the way a cache fill from an out-of-order peer would.
+    let cache_path = temp_path("cache.bao");
+    let cache_file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&cache_path)
+        .unwrap();
+    cache_file.set_len(raw_encoded.len() as u64).unwrap();
+    let mut writer_side = bao::decode::Reader::new(cache_file, hash);
+    let copied = writer_side.write_from_at(&mut src, raw_encoded.len(), 0).unwrap();
+    assert_eq!(raw_encoded.len(), copied);
+
+    let readback_file = File::open(&cache_path).unwrap();
+    let mut reader = bao::decode::Reader::new(readback_file, hash);
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).unwrap();
+    assert_eq!(input, output);
+
+    fs::remove_file(&src_path).unwrap();
+    fs::remove_file(&cache_path).unwrap();
+}
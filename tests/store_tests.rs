@@ -0,0 +1,72 @@
+//! Behavioral tests for the content-addressed chunk store (`encode::store`/`decode::store`):
+//! identical chunks across the input collapse to one stored object, and a tampered or
+//! swapped-in object fails verification instead of silently reconstructing bad content.
+
+extern crate bao;
+
+use bao::encode::store::ChunkSink;
+use bao::hash::Hash;
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct MapSink {
+    objects: HashMap<Hash, Vec<u8>>,
+    put_count: usize,
+}
+
+impl ChunkSink for MapSink {
+    fn put(&mut self, hash: &Hash, bytes: &[u8]) -> std::io::Result<()> {
+        self.put_count += 1;
+        self.objects.insert(*hash, bytes.to_vec());
+        Ok(())
+    }
+}
+
+impl bao::decode::store::ChunkSource for MapSink {
+    fn get(&self, hash: &Hash) -> std::io::Result<Vec<u8>> {
+        self.objects
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no such object"))
+    }
+}
+
+#[test]
+fn test_encode_store_dedups_identical_chunks() {
+    // Two chunks, byte-for-byte identical, should collapse to a single stored object: one for
+    // the repeated chunk, one for the parent node that combines it with itself.
+    let chunk = vec![7u8; bao::hash::CHUNK_SIZE];
+    let input: Vec<u8> = chunk.iter().chain(chunk.iter()).cloned().collect();
+
+    let mut sink = MapSink::default();
+    let (hash, infos) = bao::encode::store::encode_store(&input, &mut sink).unwrap();
+
+    // put() is called once per walked node (left chunk, right chunk, parent), but since both
+    // chunks hash to the same key, the underlying map collapses to just that one chunk object
+    // plus the parent object.
+    assert_eq!(3, sink.put_count);
+    assert_eq!(2, sink.objects.len());
+    assert_eq!(3, infos.len()); // left chunk, right chunk, parent
+
+    let mut reader = bao::decode::store::Reader::new(&sink, hash);
+    let output = reader.read_all(input.len() as u64).unwrap();
+    assert_eq!(input, output);
+}
+
+#[test]
+fn test_decode_store_rejects_tampered_object() {
+    let chunk_a = vec![1u8; bao::hash::CHUNK_SIZE];
+    let chunk_b = vec![2u8; bao::hash::CHUNK_SIZE];
+    let input: Vec<u8> = chunk_a.iter().chain(chunk_b.iter()).cloned().collect();
+
+    let mut sink = MapSink::default();
+    let (hash, _) = bao::encode::store::encode_store(&input, &mut sink).unwrap();
+
+    // Tamper with one of the stored chunk objects in place, simulating a corrupt or malicious
+    // ChunkSource that hands back the wrong bytes for a key it still claims to own.
+    let tampered_key = *sink.objects.keys().find(|k| **k != hash).unwrap();
+    sink.objects.get_mut(&tampered_key).unwrap()[0] ^= 1;
+
+    let mut reader = bao::decode::store::Reader::new(&sink, hash);
+    assert!(reader.read_all(input.len() as u64).is_err());
+}